@@ -0,0 +1,194 @@
+use core_affinity::CoreId;
+use ndarray::Array2;
+
+use crate::topology::Topology;
+
+/// Makes a latency matrix symmetric by averaging (i,j) and (j,i), ignoring NaNs
+pub fn symmetrize(latency: &Array2<f64>) -> Array2<f64> {
+    Array2::from_shape_fn(latency.dim(), |(i, j)| {
+        match (latency[(i, j)], latency[(j, i)]) {
+            (a, b) if a.is_nan() => b,
+            (a, b) if b.is_nan() => a,
+            (a, b) => (a + b) / 2.0,
+        }
+    })
+}
+
+/// Average latency between two groups of cores, ignoring pairs that were not measured
+fn average_linkage(distance: &Array2<f64>, a: &[usize], b: &[usize]) -> f64 {
+    let (sum, count) = a.iter()
+        .flat_map(|&i| b.iter().map(move |&j| distance[(i, j)]))
+        .filter(|d| !d.is_nan())
+        .fold((0.0, 0), |(sum, count), d| (sum + d, count + 1));
+    if count == 0 { f64::INFINITY } else { sum / count as f64 }
+}
+
+/// Orders the cores so that cores with low latencies between them end up next to each other.
+/// We do an average linkage hierarchical clustering, and return the leaves of the dendrogram.
+/// When merging two clusters, they are flipped to put their closest ends next to each other.
+pub fn cluster_order(latency: &Array2<f64>) -> Vec<usize> {
+    let distance = symmetrize(latency);
+    let mut clusters = (0..distance.nrows()).map(|i| vec![i]).collect::<Vec<_>>();
+
+    while clusters.len() > 1 {
+        let mut closest = (0, 1, f64::INFINITY);
+        for a in 0..clusters.len() {
+            for b in (a+1)..clusters.len() {
+                let d = average_linkage(&distance, &clusters[a], &clusters[b]);
+                if d < closest.2 {
+                    closest = (a, b, d);
+                }
+            }
+        }
+
+        let (a, b, _) = closest;
+        let mut right = clusters.remove(b);
+        let left = &mut clusters[a];
+
+        let d = |i: usize, j: usize| match distance[(i, j)] {
+            d if d.is_nan() => f64::INFINITY,
+            d => d,
+        };
+        let (l_first, l_last) = (left[0], left[left.len()-1]);
+        let (r_first, r_last) = (right[0], right[right.len()-1]);
+        let joins = [d(l_last, r_first), d(l_last, r_last), d(l_first, r_first), d(l_first, r_last)];
+        let best = (0..joins.len()).min_by(|&x, &y| joins[x].total_cmp(&joins[y])).unwrap();
+        if best == 1 || best == 3 {
+            right.reverse();
+        }
+        if best == 2 || best == 3 {
+            left.reverse();
+        }
+        left.extend(right);
+    }
+
+    clusters.pop().unwrap_or_default()
+}
+
+/// Two consecutive pair latencies further apart than this ratio belong to different tiers
+const TIER_GAP_RATIO: f64 = 1.3;
+
+/// A latency tier, and the groups of cores that are connected by latencies up to this tier
+pub struct Tier {
+    /// Median latency of the pairs in the tier
+    pub latency: f64,
+    /// Groups of core indices
+    pub groups: Vec<Vec<usize>>,
+}
+
+/// Groups cores connected by pairs for which `connected` is true
+fn connected_groups(n: usize, connected: impl Fn(usize, usize) -> bool) -> Vec<Vec<usize>> {
+    let mut group_of = (0..n).collect::<Vec<_>>();
+    for i in 0..n {
+        for j in 0..i {
+            if connected(i, j) && group_of[i] != group_of[j] {
+                let (from, to) = (group_of[i], group_of[j]);
+                group_of.iter_mut().filter(|g| **g == from).for_each(|g| *g = to);
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for i in 0..n {
+        match groups.iter_mut().find(|g| group_of[g[0]] == group_of[i]) {
+            Some(group) => group.push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+    groups
+}
+
+/// Splits the pair latencies into tiers at large gaps, e.g., intra-CCX and inter-CCX latencies.
+/// The groups of each tier are the cores connected by pairs of this tier or lower ones.
+pub fn infer_tiers(latency: &Array2<f64>) -> Vec<Tier> {
+    let distance = symmetrize(latency);
+    let n = distance.nrows();
+
+    let mut values = (0..n)
+        .flat_map(|i| (0..i).map(move |j| (i, j)))
+        .map(|p| distance[p])
+        .filter(|v| !v.is_nan())
+        .collect::<Vec<_>>();
+    values.sort_by(f64::total_cmp);
+
+    let mut tiers = Vec::new();
+    let mut start = 0;
+    for end in 1..=values.len() {
+        if end == values.len() || values[end] > values[end-1] * TIER_GAP_RATIO {
+            let upper = values[end-1];
+            tiers.push(Tier {
+                latency: crate::stats::percentile(&values[start..end], 50.0),
+                groups: connected_groups(n, |i, j| distance[(i, j)] <= upper),
+            });
+            start = end;
+        }
+    }
+    tiers
+}
+
+fn describe_groups(groups: &[Vec<usize>]) -> String {
+    let size = groups[0].len();
+    if groups.iter().all(|g| g.len() == size) {
+        format!("{} groups of {}", groups.len(), size)
+    } else {
+        let sizes = groups.iter().map(|g| g.len().to_string()).collect::<Vec<_>>();
+        format!("{} groups of {}", groups.len(), sizes.join("/"))
+    }
+}
+
+/// Describes the tiers, e.g., "2 tiers: intra-group 17ns (2 groups of 8), inter-group 85ns"
+pub fn describe_tiers(tiers: &[Tier]) -> String {
+    match tiers {
+        [] => "no pairs".to_string(),
+        [tier] => format!("1 tier: {:.0}ns", tier.latency),
+        [first, .., last] if tiers.len() == 2 => format!("2 tiers: intra-group {:.0}ns ({}), inter-group {:.0}ns",
+            first.latency, describe_groups(&first.groups), last.latency),
+        [.., last] => {
+            let within = tiers[..tiers.len()-1].iter()
+                .map(|t| format!("{:.0}ns ({})", t.latency, describe_groups(&t.groups)))
+                .collect::<Vec<_>>();
+            format!("{} tiers: within groups {}, between groups {:.0}ns", tiers.len(), within.join(", "), last.latency)
+        }
+    }
+}
+
+/// Compares the inferred groups with the cores sharing an L3 cache according to the kernel.
+/// Returns None when a tier matches, otherwise describes the disagreement with the closest tier.
+pub fn compare_with_l3(tiers: &[Tier], cores: &[CoreId], topology: &Topology) -> Option<String> {
+    let l3 = |i: usize| topology.get(cores[i].id).and_then(|c| c.l3);
+    let n = cores.len();
+    let same_l3 = |i, j| l3(i).is_some() && l3(i) == l3(j);
+    if (0..n).all(|i| l3(i).is_none()) {
+        return None;
+    }
+
+    let mismatches = |groups: &[Vec<usize>]| {
+        let mut group_of = vec![0; n];
+        for (g, group) in groups.iter().enumerate() {
+            group.iter().for_each(|&i| group_of[i] = g);
+        }
+        (0..n).flat_map(|i| (0..i).map(move |j| (i, j)))
+            .filter(|&(i, j)| (group_of[i] == group_of[j]) != same_l3(i, j))
+            .collect::<Vec<_>>()
+    };
+
+    // The last tier is everything in one group, it's not a candidate
+    let candidates = &tiers[..tiers.len().saturating_sub(1)];
+    let closest = candidates.iter().enumerate()
+        .map(|(k, t)| (k, mismatches(&t.groups)))
+        .min_by_key(|(_, m)| m.len());
+
+    let l3_groups = connected_groups(n, same_l3);
+    let format_groups = |groups: &[Vec<usize>]| groups.iter()
+        .map(|g| format!("{{{}}}", g.iter().map(|&i| cores[i].id.to_string()).collect::<Vec<_>>().join(",")))
+        .collect::<Vec<_>>().join(" ");
+
+    match closest {
+        Some((_, m)) if m.is_empty() => None,
+        Some((k, m)) => Some(format!("{} pairs disagree with tier {}: inferred {} vs kernel L3 {}",
+            m.len(), k+1, format_groups(&tiers[k].groups), format_groups(&l3_groups))),
+        None if l3_groups.len() > 1 => Some(format!("no groups inferred, but the kernel reports L3 domains {}",
+            format_groups(&l3_groups))),
+        None => None,
+    }
+}
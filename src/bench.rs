@@ -1,121 +1,598 @@
 pub mod cas;
+pub mod false_sharing;
 pub mod read_write;
 pub mod msg_passing;
+pub mod ops;
+pub mod payload;
 
-use ansi_term::Color;
 use core_affinity::CoreId;
 use quanta::Clock;
-use std::io::Write;
-use ndarray::{s, Axis};
-use ordered_float::NotNan;
-use crate::CliArgs;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use ndarray::{s, Array2, Array3, Axis};
+use crate::budget::{self, Cost, Eta, Fit};
+use self::ops::{MemoryOrdering, Op};
+use crate::checkpoint::Checkpoint;
+use crate::cpulist::SmtMode;
+use crate::error::{Error, Result};
+use crate::pairs::{self, PairSampling};
+use crate::stats::{self, Bootstrap, OutlierFilter};
+use crate::topology::{self, Topology};
+use crate::utils;
 
 pub type Count = u32;
 
+pub const DEFAULT_NUM_SAMPLES: Count = 300;
+pub const DEFAULT_NUM_ITERATIONS_PER_SAMPLE: Count = 1000;
+
+/// Samples indexed by (i, j, sample). Pairs that are not measured are NaN.
+pub type Results = Array3<f64>;
+
+/// The results of one benchmark
+pub struct LatencyMatrix {
+    /// The name of the bench, followed by the value of its sweep for the benches with one, e.g., payload-4
+    pub name: String,
+    /// The cores of the rows and columns of the results
+    pub cores: Vec<CoreId>,
+    /// Whether the bench on (i,j) is the same as the bench on (j,i), in which case only the lower triangle is measured.
+    /// Otherwise, (i,j) is measured with core i receiving the messages of core j.
+    pub symmetric: bool,
+    /// All the samples that were measured
+    pub raw: Results,
+    /// The samples once outliers are rejected, which are set to NaN
+    pub results: Results,
+    /// The warmup samples taken before the samples of each pair
+    pub warmup: Results,
+    /// Confidence intervals, when bootstrapping is enabled
+    pub bootstrap: Option<Bootstrap>,
+    /// The parameters of the measurement, once scaled down to the time budget
+    pub config: Config,
+    /// With `parallel`, how much measuring pairs concurrently disturbs a pair
+    pub interference: Option<Interference>,
+}
+
+fn count_samples(results: &Results) -> Array2<usize> {
+    results.map_axis(Axis(2), |lane| lane.iter().filter(|v| !v.is_nan()).count())
+}
+
+impl LatencyMatrix {
+    /// The number of samples measured for each pair
+    pub fn num_samples(&self) -> Array2<usize> {
+        count_samples(&self.raw)
+    }
+
+    /// The number of samples rejected as outliers for each pair
+    pub fn dropped(&self) -> Array2<usize> {
+        count_samples(&self.raw) - count_samples(&self.results)
+    }
+
+    /// Permutes the cores of the rows and columns. `order` lists the current indices in their new order.
+    pub fn reorder(&mut self, order: &[usize]) {
+        let permute = |r: &Results| r.select(Axis(0), order).select(Axis(1), order);
+        self.cores = order.iter().map(|&i| self.cores[i]).collect();
+        self.raw = permute(&self.raw);
+        self.results = permute(&self.results);
+        self.warmup = permute(&self.warmup);
+
+        // Symmetric benches only measure the lower triangle, we keep it that way.
+        // The other benches measure (i,j) and (j,i) separately, they can't be folded.
+        if !self.symmetric {
+            return;
+        }
+        let n = self.cores.len();
+        for i in 0..n {
+            for j in (i+1)..n {
+                let measured = |r: &Results, i, j| r.slice(s![i, j, ..]).iter().any(|v| !v.is_nan());
+                if measured(&self.raw, i, j) && !measured(&self.raw, j, i) {
+                    for r in [&mut self.raw, &mut self.results, &mut self.warmup] {
+                        let upper = r.slice(s![i, j, ..]).to_owned();
+                        r.slice_mut(s![j, i, ..]).assign(&upper);
+                        r.slice_mut(s![i, j, ..]).fill(f64::NAN);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The first pair measured concurrently with other pairs, measured again alone at the end
+pub struct Interference {
+    pub cores: (CoreId, CoreId),
+    /// The samples measured concurrently with other pairs, with outliers set to NaN
+    pub concurrent: Vec<f64>,
+    /// The samples measured alone, with outliers set to NaN
+    pub alone: Vec<f64>,
+}
+
 pub trait Bench {
-    fn run(&self, cores: (CoreId, CoreId), clock: &Clock, num_iterations: Count, num_samples: Count) -> Vec<f64>;
-    /// Whether the bench on (i,j) is the same as the bench on (j,i)
-    fn is_symmetric(&self) -> bool { true }
+    /// Returns one sample per `num_samples`, in nanoseconds per iteration
+    fn run(&self, cores: (CoreId, CoreId), clock: &Clock, num_iterations: Count, num_samples: Count) -> Result<Vec<f64>>;
+}
+
+/// What a bench needs from the machine to give meaningful results
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Requirement {
+    /// The bench compares clock readings taken on different cores
+    InvariantTsc,
+}
+
+impl Requirement {
+    pub fn name(self) -> &'static str {
+        match self {
+            Requirement::InvariantTsc => "invariant TSC",
+        }
+    }
+
+    pub fn check(self, clock: &Clock) -> Result<()> {
+        match self {
+            Requirement::InvariantTsc => utils::check_rdtsc_usable(clock),
+        }
+    }
 }
 
-pub fn run_bench(cores: &[CoreId], clock: &Clock, args: &CliArgs, bench: impl Bench) {
-    let num_samples = args.num_samples;
-    let num_iterations = args.num_iterations;
+/// A benchmark that can be selected by name. Each bench module declares its own `KIND`,
+/// and new benches are made available by adding it to `BENCHES`.
+#[derive(Debug)]
+pub struct BenchKind {
+    /// A stable name, used to select the bench and to label its results
+    pub name: &'static str,
+    /// A one-line description, shown as the title of the results
+    pub description: &'static str,
+    pub requirements: &'static [Requirement],
+    /// Whether the bench on (i,j) is the same as the bench on (j,i).
+    /// Otherwise, (i,j) is measured with core i receiving the messages of core j.
+    pub symmetric: bool,
+    /// Creates the state of the bench. Pairs measured concurrently each get their own.
+    pub new: fn(&Config) -> Box<dyn Bench + Sync>,
+    /// The parameter that the bench is run over, once per value
+    pub sweep: Option<Sweep>,
+}
+
+/// A parameter of a bench that is measured at several values, to compare them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Sweep {
+    /// The size of the payload, `Config::payload_lines`
+    PayloadLines,
+    /// The distance between the counters, `Config::false_sharing_offset`
+    FalseSharingOffset,
+}
+
+impl Sweep {
+    pub fn name(self) -> &'static str {
+        match self {
+            Sweep::PayloadLines => "payload size",
+            Sweep::FalseSharingOffset => "offset",
+        }
+    }
+
+    /// The header of the values in a table
+    pub fn label(self) -> &'static str {
+        match self {
+            Sweep::PayloadLines => "Lines",
+            Sweep::FalseSharingOffset => "Bytes",
+        }
+    }
+
+    /// The value of a config
+    pub fn value(self, config: &Config) -> usize {
+        match self {
+            Sweep::PayloadLines => config.payload_lines,
+            Sweep::FalseSharingOffset => config.false_sharing_offset,
+        }
+    }
+
+    /// A config with the given value
+    pub fn with(self, config: Config, value: usize) -> Config {
+        match self {
+            Sweep::PayloadLines => Config { payload_lines: value, ..config },
+            Sweep::FalseSharingOffset => Config { false_sharing_offset: value, ..config },
+        }
+    }
+
+    /// The value that the others are compared to: the smallest payload, or the counters furthest apart
+    pub fn baseline(self, values: impl Iterator<Item = usize>) -> Option<usize> {
+        match self {
+            Sweep::PayloadLines => values.min(),
+            Sweep::FalseSharingOffset => values.max(),
+        }
+    }
+}
+
+impl BenchKind {
+    pub fn make(&self, config: &Config) -> Box<dyn Bench + Sync> {
+        (self.new)(config)
+    }
+
+    /// Checks that the machine meets the requirements of the bench
+    pub fn check(&self, clock: &Clock) -> Result<()> {
+        self.requirements.iter().try_for_each(|r| r.check(clock))
+    }
+
+    /// Finds a bench by name, or by its position in `BENCHES` starting from 1, as in older versions
+    pub fn find(name: &str) -> std::result::Result<&'static BenchKind, String> {
+        let by_number = name.parse::<usize>().ok().and_then(|n| BENCHES.get(n.wrapping_sub(1)));
+        by_number.or_else(|| BENCHES.iter().find(|b| b.name == name)).copied().ok_or_else(|| format!(
+            "Unknown bench '{}', expected one of: {}", name, BENCHES.iter().map(|b| b.name).collect::<Vec<_>>().join(", ")))
+    }
+}
+
+/// The benchmarks that `run_matrix` can run
+pub static BENCHES: &[&BenchKind] = &[
+    &cas::KIND,
+    &read_write::KIND,
+    &msg_passing::KIND,
+    &false_sharing::KIND,
+    &payload::KIND,
+];
+
+/// How to measure a matrix
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub bench: &'static BenchKind,
+    /// The number of iterations per sample
+    pub num_iterations: Count,
+    /// The number of samples per pair
+    pub num_samples: Count,
+    /// The number of samples discarded before measuring each pair
+    pub warmup_samples: Count,
+    /// Warms up each pair for this many milliseconds before the warmup samples
+    pub warmup_ms: u64,
+    /// Keeps sampling each pair in batches of `num_samples` until the standard deviation
+    /// of its mean falls below this many nanoseconds, or `max_samples` is reached
+    pub target_stderr: Option<f64>,
+    /// The maximum number of samples per pair with `target_stderr`. Defaults to 10x `num_samples`
+    pub max_samples: Option<Count>,
+    /// How to reject the samples disturbed by interrupts or preemption
+    pub outliers: OutlierFilter,
+    /// Takes the samples of each pair in this many passes over all the pairs, in a shuffled order
+    pub passes: Count,
+    /// Measures up to this many pairs at the same time, on disjoint cores
+    pub parallel: usize,
+    /// Which pairs of cores to measure
+    pub pairs: PairSampling,
+    /// The number of pairs measured with `PairSampling::Random`
+    pub num_pairs: usize,
+    /// The number of pairs measured per topology relationship with `PairSampling::Class`
+    pub pairs_per_class: usize,
+    /// The operation that writes the shared line in the ping-pong benches, or the default of each bench
+    pub op: Option<Op>,
+    /// The memory ordering of the ping-pong benches, or the default of each bench
+    pub ordering: Option<MemoryOrdering>,
+    /// The distance between the two counters of the false-sharing bench, in bytes
+    pub false_sharing_offset: usize,
+    /// The size of the payload of the payload bench, in cache lines
+    pub payload_lines: usize,
+    /// How to handle hyper-threads of the same physical core
+    pub smt: SmtMode,
+    /// The seed of the random pair order and pair sampling
+    pub seed: u64,
+    /// Scales the parameters down so that the measurement takes about this long
+    pub time_budget: Option<Duration>,
+}
 
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bench: &cas::KIND,
+            num_iterations: DEFAULT_NUM_ITERATIONS_PER_SAMPLE,
+            num_samples: DEFAULT_NUM_SAMPLES,
+            warmup_samples: 1,
+            warmup_ms: 0,
+            target_stderr: None,
+            max_samples: None,
+            outliers: OutlierFilter::None,
+            passes: 1,
+            parallel: 1,
+            pairs: PairSampling::All,
+            num_pairs: 100,
+            pairs_per_class: 1,
+            op: None,
+            ordering: None,
+            false_sharing_offset: false_sharing::DEFAULT_OFFSETS[0],
+            payload_lines: 1,
+            smt: SmtMode::Include,
+            seed: 0,
+            time_budget: None,
+        }
+    }
+}
+
+impl Config {
+    /// The name of the bench, followed by the value of its sweep for the benches with one
+    pub fn name(&self) -> String {
+        match self.bench.sweep {
+            Some(sweep) => format!("{}-{}", self.bench.name, sweep.value(self)),
+            None => self.bench.name.to_string(),
+        }
+    }
+
+    /// The maximum number of samples per pair
+    pub fn max_samples(&self) -> Count {
+        match self.target_stderr {
+            Some(_) => self.max_samples.unwrap_or(10*self.num_samples).max(self.num_samples),
+            None => self.num_samples,
+        }
+    }
+}
+
+struct PairSamples {
+    samples: Vec<f64>,
+    /// The same samples with outliers set to NaN
+    kept: Vec<f64>,
+    /// The warmup samples of the first batch
+    warmup: Vec<f64>,
+}
+
+impl PairSamples {
+    /// Samples read back from a checkpoint
+    fn from_samples(samples: &[f64], warmup: &[f64], outliers: OutlierFilter) -> Self {
+        PairSamples { samples: samples.to_vec(), kept: outliers.reject(samples), warmup: warmup.to_vec() }
+    }
+
+    /// Appends the samples of a later pass, and rejects outliers again over all the samples
+    fn merge(&mut self, other: PairSamples, outliers: OutlierFilter) {
+        self.samples.extend(other.samples);
+        self.kept = outliers.reject(&self.samples);
+    }
+}
+
+/// Measures a pair, after warming up for `warmup_ms` and `warmup_samples`.
+/// With a target standard error, samples are taken in batches of `num_samples`
+/// until the target or `max_samples` is reached.
+fn measure_pair(bench: &dyn Bench, cores: (CoreId, CoreId), clock: &Clock, config: &Config) -> Result<PairSamples> {
+    // Time based warmup. Each run spawns new threads, but it gets the cores to ramp up their frequency.
+    let warmup_start = Instant::now();
+    while warmup_start.elapsed() < Duration::from_millis(config.warmup_ms) {
+        bench.run(cores, clock, config.num_iterations, 1)?;
+    }
+
+    let max_samples = config.max_samples() as usize;
+    let num_warmup = config.warmup_samples as usize;
+    let mut samples = Vec::with_capacity(max_samples);
+    let mut warmup = Vec::new();
+
+    loop {
+        let batch_size = (config.num_samples as usize).min(max_samples - samples.len());
+        // Each batch starts with warmup samples that we discard
+        let durations = bench.run(cores, clock, config.num_iterations, (num_warmup + batch_size) as Count)?;
+        if warmup.is_empty() {
+            warmup.extend_from_slice(&durations[..num_warmup]);
+        }
+        samples.extend_from_slice(&durations[num_warmup..]);
+
+        let kept = config.outliers.reject(&samples);
+        let target_reached = match config.target_stderr {
+            Some(target) => stats::stderr(&stats::sorted_values(&kept)) <= target,
+            None => true,
+        };
+
+        if target_reached || samples.len() >= max_samples {
+            return Ok(PairSamples { samples, kept, warmup });
+        }
+    }
+}
+
+/// Measures a few samples of a pair, to estimate how long the other pairs will take
+fn probe_cost(bench: &dyn Bench, cores: (CoreId, CoreId), clock: &Clock, config: &Config) -> Result<Cost> {
+    let probe_config = Config { num_samples: config.num_samples.min(10), target_stderr: None, warmup_ms: 0, ..config.clone() };
+    let start = Instant::now();
+    measure_pair(bench, cores, clock, &probe_config)?;
+    Ok(Cost::new(start.elapsed(), &probe_config))
+}
+
+/// Measures the pairs of a batch at the same time, each with its own bench state
+fn measure_concurrently(
+    batch: &[(usize, usize)],
+    cores: &[CoreId],
+    clock: &Clock,
+    config: &Config,
+) -> Result<Vec<PairSamples>> {
+    let benches = batch.iter().map(|_| config.bench.make(config)).collect::<Vec<_>>();
+
+    crossbeam_utils::thread::scope(|s| {
+        let handles = batch.iter().zip(&benches)
+            .map(|(&(i, j), bench)| s.spawn(move |_| measure_pair(bench.as_ref(), (cores[i], cores[j]), clock, config)))
+            .collect::<Vec<_>>();
+        handles.into_iter().map(|h| h.join().map_err(Error::thread_panicked)?).collect()
+    }).map_err(Error::thread_panicked)?
+}
+
+/// What happens while measuring a matrix, to show the progress
+pub enum Event<'a> {
+    /// The parameters were scaled down to fit the time budget
+    Scaled(&'a Fit),
+    /// A pair was measured, or read back from the checkpoint
+    Measured {
+        pair: (usize, usize),
+        /// The samples of this pass, with outliers set to NaN
+        samples: &'a [f64],
+        /// Starting from 0
+        pass: Count,
+        /// The pairs measured so far in this pass
+        num_measured: usize,
+        num_pairs: usize,
+        eta: &'a Eta,
+    },
+}
+
+/// Measures the pairs of cores selected by the config, and returns all the samples.
+/// The topology is read from /sys when available.
+pub fn run_matrix(cores: &[CoreId], clock: &Clock, config: &Config) -> Result<LatencyMatrix> {
+    let topology = Topology::from_sysfs(Path::new(topology::DEFAULT_SYSFS_ROOT)).ok();
+    measure_matrix(cores, topology.as_ref(), clock, config, None, |_| {})
+}
+
+/// Like `run_matrix`, with the topology used to select and schedule pairs, and a callback to follow the progress.
+/// Without `passes` and `parallel`, pairs are measured one at a time, row by row.
+/// Pairs found in the checkpoint are not measured again, and the measured pairs are added to it.
+pub fn measure_matrix(
+    cores: &[CoreId],
+    topology: Option<&Topology>,
+    clock: &Clock,
+    config: &Config,
+    mut checkpoint: Option<&mut Checkpoint>,
+    mut on_event: impl FnMut(Event),
+) -> Result<LatencyMatrix> {
+    let name = config.name();
+    config.bench.check(clock)?;
     let n_cores = cores.len();
-    assert!(n_cores >= 2);
-    let shape = ndarray::Ix3(n_cores, n_cores, num_samples as usize);
-    let mut results = ndarray::Array::from_elem(shape, f64::NAN);
+    utils::check_cores(cores)?;
+    if config.parallel == 0 {
+        return Err(Error::InvalidArgs("At least 1 pair should be measured at a time".to_string()));
+    }
 
-    // First print the column header
-    eprint!("    {: >3}", "");
-    for j in cores {
-        eprint!(" {: >4}{: >3}", j.id, "");
-        //        |||
-        //        ||+-- Width
-        //        |+--- Align
-        //        +---- Fill
-    }
-    eprintln!();
-
-    let mcolor = Color::White.bold();
-    let scolor = Color::White.dimmed();
-
-    // Do the benchmark
-    for i in 0..n_cores {
-        let core_i = cores[i];
-        eprint!("    {: >3}", core_i.id);
-        for j in 0..n_cores {
-            if bench.is_symmetric() {
-                if i <= j {
-                   continue;
-                }
-            } else if i == j {
-                eprint!("{: >8}", "");
-                continue;
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut selected = pairs::select_pairs(cores, topology, config, config.bench.symmetric, &mut rng)?;
+    let num_pairs = selected.iter().filter(|&&s| s).count();
+    if num_pairs == 0 {
+        return Err(Error::InvalidArgs("No pairs of cores are selected".to_string()));
+    }
+
+    // Scale the parameters down to fit the time budget
+    let fit;
+    let config = match (config.time_budget, selected.indexed_iter().find(|(_, &s)| s)) {
+        (Some(budget), Some(((i, j), _))) => {
+            let cost = probe_cost(config.bench.make(config).as_ref(), (cores[i], cores[j]), clock, config)?;
+            fit = budget::fit(config, &cost, num_pairs, budget);
+            if fit.config.pairs != config.pairs || fit.config.num_pairs != config.num_pairs {
+                selected = pairs::select_pairs(cores, topology, &fit.config, config.bench.symmetric, &mut rng)?;
             }
+            on_event(Event::Scaled(&fit));
+            &fit.config
+        }
+        _ => config,
+    };
+    // The state of some benches depends on the scaled parameters, e.g., the clocks of msg-passing
+    let bench = config.bench.make(config);
+    let pairs = selected.indexed_iter().filter(|(_, &s)| s).map(|(p, _)| p).collect::<Vec<_>>();
+    let num_pairs = pairs.len();
 
-            let core_j = cores[j];
-            // We add 1 warmup cycle first
-            let durations = bench.run((core_i, core_j), clock, num_iterations, 1+num_samples);
-            let durations = &durations[1..];
-            let mut values = results.slice_mut(s![i,j,..]);
-            for s in 0..num_samples as usize {
-                values[s] = durations[s]
+    let mut measured: BTreeMap<(usize, usize), PairSamples> = BTreeMap::new();
+    let mut add = |p, pair: PairSamples| match measured.get_mut(&p) {
+        Some(previous) => previous.merge(pair, config.outliers),
+        None => { measured.insert(p, pair); }
+    };
+
+    // The first pair measured along with other pairs is our control pair, we measure it again alone at the end.
+    // Batches of a single pair are measured alone, they tell nothing about the interference.
+    let mut control = None;
+    let mut eta = Eta::new(num_pairs * config.passes as usize);
+    for pass in 0..config.passes {
+        // The first passes take the remaining samples
+        let num_samples = config.num_samples / config.passes + Count::from(pass < config.num_samples % config.passes);
+        let pass_config = Config { num_samples, ..config.clone() };
+
+        let mut order = pairs.clone();
+        if config.passes > 1 {
+            order.shuffle(&mut rng);
+        }
+
+        let mut num_measured = 0;
+        for batch in pairs::schedule_batches(order, cores, topology, config.parallel) {
+            let resumed = |&(i, j): &(usize, usize)| checkpoint.as_deref()
+                .and_then(|c| c.get(&name, pass, (cores[i], cores[j])))
+                .map(|(samples, warmup)| PairSamples::from_samples(samples, warmup, config.outliers));
+
+            // Pairs found in the checkpoint take no time, they are not part of the ETA
+            let mut batch_samples = batch.iter().filter_map(|p| Some((*p, resumed(p)?))).collect::<Vec<_>>();
+            let batch = batch.into_iter().filter(|p| resumed(p).is_none()).collect::<Vec<_>>();
+            eta.skipped(batch_samples.len());
+
+            if !batch.is_empty() {
+                let samples = measure_concurrently(&batch, cores, clock, &pass_config)?;
+                if control.is_none() && batch.len() > 1 {
+                    control = Some((batch[0], samples[0].kept.clone()));
+                }
+                eta.measured(batch.len());
+                for ((i, j), pair) in batch.into_iter().zip(samples) {
+                    if let Some(checkpoint) = checkpoint.as_deref_mut() {
+                        checkpoint.record(&name, pass, (cores[i], cores[j]), &pair.samples, &pair.warmup)?;
+                    }
+                    batch_samples.push(((i, j), pair));
+                }
             }
 
-            let mean = format!("{: >4.0}", values.mean().unwrap());
-            // We apply the central limit theorem to estimate the standard deviation
-            let stddev = format!("±{: <2.0}", values.std(1.0).min(99.0) / (num_samples as f64).sqrt());
-            eprint!(" {}{}", mcolor.paint(mean), scolor.paint(stddev));
-            let _ = std::io::stdout().lock().flush();
+            // Report the pairs in the order of the rows
+            batch_samples.sort_by_key(|&(p, _)| p);
+            for (p, pair) in batch_samples {
+                num_measured += 1;
+                on_event(Event::Measured { pair: p, samples: &pair.kept, pass, num_measured, num_pairs, eta: &eta });
+                add(p, pair);
+            }
         }
-        eprintln!();
     }
 
-    eprintln!();
+    let shape = ndarray::Ix3(n_cores, n_cores, config.max_samples() as usize);
+    let mut raw = ndarray::Array::from_elem(shape, f64::NAN);
+    let mut results = ndarray::Array::from_elem(shape, f64::NAN);
+    let mut warmup = ndarray::Array::from_elem((n_cores, n_cores, config.warmup_samples as usize), f64::NAN);
+    for (&(i, j), pair) in &measured {
+        raw.slice_mut(s![i,j,..pair.samples.len()]).assign(&ndarray::aview1(&pair.samples));
+        results.slice_mut(s![i,j,..pair.kept.len()]).assign(&ndarray::aview1(&pair.kept));
+        warmup.slice_mut(s![i,j,..]).assign(&ndarray::aview1(&pair.warmup));
+    }
 
-    // Print min/max latency
-    {
-        let mean = results.mean_axis(Axis(2)).unwrap();
-        let stddev = results.std_axis(Axis(2), 1.0) / (num_samples as f64).sqrt();
+    let interference = match control {
+        Some(((i, j), concurrent)) => Some(Interference {
+            cores: (cores[i], cores[j]),
+            concurrent,
+            alone: measure_pair(bench.as_ref(), (cores[i], cores[j]), clock, config)?.kept,
+        }),
+        None => None,
+    };
 
-        let ((min_i, min_j), _) = mean.indexed_iter()
-            .filter_map(|(i, v)| NotNan::new(*v).ok().map(|v| (i, v)))
-            .min_by_key(|(_, v)| *v)
-            .unwrap();
-        let min_mean = format!("{:.1}", mean[(min_i, min_j)]);
-        let min_stddev = format!("±{:.1}", stddev[(min_i, min_j)]);
-        let (min_core_id_i, min_core_id_j) = (cores[min_i].id, cores[min_j].id);
+    Ok(LatencyMatrix {
+        name,
+        cores: cores.to_vec(),
+        symmetric: config.bench.symmetric,
+        raw,
+        results,
+        warmup,
+        bootstrap: None,
+        config: config.clone(),
+        interference,
+    })
+}
 
-        let ((max_i, max_j), _) = mean.indexed_iter()
-            .filter_map(|(i, v)| NotNan::new(*v).ok().map(|v| (i, v)))
-            .max_by_key(|(_, v)| *v)
-            .unwrap();
-        let max_mean = format!("{:.1}", mean[(max_i, max_j)]);
-        let max_stddev = format!("±{:.1}", stddev[(max_i, max_j)]);
-        let (max_core_id_i, max_core_id_j) = (cores[max_i].id, cores[max_j].id);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        eprintln!("    Min  latency: {}ns {} cores: ({},{})", mcolor.paint(min_mean), scolor.paint(min_stddev), min_core_id_i, min_core_id_j);
-        eprintln!("    Max  latency: {}ns {} cores: ({},{})", mcolor.paint(max_mean), scolor.paint(max_stddev), max_core_id_i, max_core_id_j);
+    /// 2 cores, with only (0,1) measured
+    fn upper_only(symmetric: bool) -> LatencyMatrix {
+        let mut raw = Results::from_elem((2, 2, 1), f64::NAN);
+        raw[(0, 1, 0)] = 1.0;
+        LatencyMatrix {
+            name: "test".to_string(),
+            cores: vec![CoreId { id: 0 }, CoreId { id: 1 }],
+            symmetric,
+            results: raw.clone(),
+            warmup: Results::from_elem((2, 2, 0), f64::NAN),
+            raw,
+            bootstrap: None,
+            config: Config::default(),
+            interference: None,
+        }
     }
 
-    // Print mean latency
-    {
-        let values = results.iter().copied().filter(|v| !v.is_nan()).collect::<Vec<_>>();
-        let values = ndarray::arr1(&values);
-        let mean = format!("{:.1}", values.mean().unwrap());
-        // no stddev, it's hard to put a value that is meaningful without a lengthy explanation
-        eprintln!("    Mean latency: {}ns", mcolor.paint(mean));
+    #[test]
+    fn reorder_keeps_symmetric_benches_in_the_lower_triangle() {
+        let mut m = upper_only(true);
+        m.reorder(&[0, 1]);
+        assert_eq!(m.raw[(1, 0, 0)], 1.0);
+        assert!(m.raw[(0, 1, 0)].is_nan());
     }
 
-    if args.csv {
-        let results = results.mean_axis(Axis(2)).unwrap();
-        for row in results.rows() {
-            let row = row.iter()
-                .map(|v| if v.is_nan() { "".to_string() } else { v.to_string() })
-                .collect::<Vec<_>>().join(",");
-            println!("{}", row);
-        }
+    #[test]
+    fn reorder_does_not_transpose_asymmetric_benches() {
+        let mut m = upper_only(false);
+        m.reorder(&[0, 1]);
+        // (0,1) is from core 0 to core 1, it must not move to (1,0)
+        assert_eq!(m.raw[(0, 1, 0)], 1.0);
+        assert!(m.raw[(1, 0, 0)].is_nan());
     }
 }
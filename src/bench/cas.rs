@@ -1,26 +1,48 @@
 use core_affinity::CoreId;
 use std::sync::Barrier;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicU64, Ordering};
 use quanta::Clock;
-use super::Count;
+use super::{BenchKind, Count};
+use super::ops::{MemoryOrdering, Op};
+use crate::error::{Error, Result};
 
-const PING: bool = false;
-const PONG: bool = true;
+pub static KIND: BenchKind = BenchKind {
+    name: "cas",
+    description: "CAS latency on a single shared cache line",
+    requirements: &[],
+    symmetric: true,
+    new: |config| Box::new(Bench::new(
+        config.op.unwrap_or(Op::CompareExchange),
+        config.ordering.unwrap_or(MemoryOrdering::Relaxed),
+    )),
+    sweep: None,
+};
 
 pub struct Bench {
     barrier: Barrier,
-    flag: AtomicBool,
+    /// Incremented by each thread in turn, pong from even values and ping from odd values
+    flag: AtomicU64,
+    op: Op,
+    ordering: MemoryOrdering,
 }
 
 impl Bench {
-    pub fn new() -> Self {
+    pub fn new(op: Op, ordering: MemoryOrdering) -> Self {
         Self {
             barrier: Barrier::new(2),
-            flag: AtomicBool::new(PING),
+            flag: AtomicU64::new(0),
+            op,
+            ordering,
         }
     }
 }
 
+impl Default for Bench {
+    fn default() -> Self {
+        Self::new(Op::CompareExchange, MemoryOrdering::Relaxed)
+    }
+}
+
 impl super::Bench for Bench {
     // The two threads modify the same cacheline.
     // This is useful to benchmark spinlock performance.
@@ -30,16 +52,19 @@ impl super::Bench for Bench {
         clock: &Clock,
         num_round_trips: Count,
         num_samples: Count,
-    ) -> Vec<f64> {
+    ) -> Result<Vec<f64>> {
         let state = self;
+        // The flag keeps counting from previous runs
+        let base = state.flag.load(Ordering::Relaxed);
 
         crossbeam_utils::thread::scope(|s| {
             let pong = s.spawn(move |_| {
                 core_affinity::set_for_current(pong_core);
 
                 state.barrier.wait();
-                for _ in 0..(num_round_trips*num_samples) {
-                    while state.flag.compare_exchange(PING, PONG, Ordering::Relaxed, Ordering::Relaxed).is_err() {}
+                for round in 0..(num_round_trips*num_samples) as u64 {
+                    let v = base + 2*round;
+                    state.op.hand_over(&state.flag, v, v+1, state.ordering);
                 }
             });
 
@@ -47,13 +72,15 @@ impl super::Bench for Bench {
                 core_affinity::set_for_current(ping_core);
 
                 let mut results = Vec::with_capacity(num_samples as usize);
+                let mut v = base + 1;
 
                 state.barrier.wait();
 
                 for _ in 0..num_samples {
                     let start = clock.raw();
                     for _ in 0..num_round_trips {
-                        while state.flag.compare_exchange(PONG, PING, Ordering::Relaxed, Ordering::Relaxed).is_err() {}
+                        state.op.hand_over(&state.flag, v, v+1, state.ordering);
+                        v += 2;
                     }
                     let end = clock.raw();
                     let duration = clock.delta(start, end).as_nanos();
@@ -63,8 +90,8 @@ impl super::Bench for Bench {
                 results
             });
 
-            pong.join().unwrap();
-            ping.join().unwrap()
-        }).unwrap()
+            pong.join().map_err(Error::thread_panicked)?;
+            ping.join().map_err(Error::thread_panicked)
+        }).map_err(Error::thread_panicked)?
     }
 }
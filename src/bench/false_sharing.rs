@@ -0,0 +1,114 @@
+use cache_padded::CachePadded;
+use core_affinity::CoreId;
+use std::sync::Barrier;
+use std::sync::atomic::{AtomicU64, Ordering};
+use quanta::Clock;
+
+use super::{BenchKind, Count, Sweep};
+use super::ops::{MemoryOrdering, Op};
+use crate::error::{Error, Result};
+
+/// The same line, adjacent lines, and separate pairs of lines
+pub const DEFAULT_OFFSETS: [usize; 3] = [8, 64, 128];
+
+/// The counters are placed within this many bytes
+const SPAN: usize = 256;
+
+pub static KIND: BenchKind = BenchKind {
+    name: "false-sharing",
+    description: "Time per write of two threads writing their own counters, on the same or nearby cache lines",
+    requirements: &[],
+    symmetric: true,
+    new: |config| Box::new(Bench::new(
+        config.false_sharing_offset,
+        config.op.unwrap_or(Op::Store),
+        config.ordering.unwrap_or(MemoryOrdering::Relaxed),
+    )),
+    sweep: Some(Sweep::FalseSharingOffset),
+};
+
+/// Parses the distance between the two counters
+pub fn parse_offset(s: &str) -> std::result::Result<usize, String> {
+    let offset = s.parse::<usize>().map_err(|e| format!("Invalid offset '{}': {}", s, e))?;
+    if offset == 0 || offset >= SPAN || offset % 8 != 0 {
+        return Err(format!("The offset should be a multiple of 8 between 8 and {}, got {}", SPAN - 8, offset));
+    }
+    Ok(offset)
+}
+
+/// Aligned on a pair of cache lines, as the adjacent line prefetcher of Intel CPUs fetches lines by pairs.
+/// An offset of 64 puts the counters on adjacent lines of the same pair, and 128 on different pairs.
+#[repr(align(128))]
+struct Counters([AtomicU64; SPAN / 8]);
+
+pub struct Bench {
+    barrier: CachePadded<Barrier>,
+    counters: Counters,
+    /// The distance between the two counters, in bytes
+    offset: usize,
+    op: Op,
+    ordering: MemoryOrdering,
+}
+
+impl Bench {
+    pub fn new(offset: usize, op: Op, ordering: MemoryOrdering) -> Self {
+        Self {
+            barrier: CachePadded::new(Barrier::new(2)),
+            counters: Counters(Default::default()),
+            offset,
+            op,
+            ordering,
+        }
+    }
+
+    /// Increments the counter `num_iterations` times per sample, and returns the time per increment
+    fn count(&self, counter: &AtomicU64, core: CoreId, clock: &Clock, num_iterations: Count, num_samples: Count) -> Vec<f64> {
+        core_affinity::set_for_current(core);
+        let mut results = Vec::with_capacity(num_samples as usize);
+        // The counter keeps counting from previous runs
+        let mut v = counter.load(Ordering::Relaxed);
+
+        self.barrier.wait();
+        for _ in 0..num_samples {
+            // Both threads write at the same time
+            self.barrier.wait();
+            let start = clock.raw();
+            for _ in 0..num_iterations {
+                self.op.write(counter, v, v+1, self.ordering);
+                v += 1;
+            }
+            let end = clock.raw();
+            results.push(clock.delta(start, end).as_nanos() as f64 / num_iterations as f64);
+        }
+        results
+    }
+}
+
+impl Default for Bench {
+    fn default() -> Self {
+        Self::new(DEFAULT_OFFSETS[0], Op::Store, MemoryOrdering::Relaxed)
+    }
+}
+
+impl super::Bench for Bench {
+    // Each thread only writes its own counter. They only interfere through the cache lines they share.
+    fn run(
+        &self,
+        (ping_core, pong_core): (CoreId, CoreId),
+        clock: &Clock,
+        num_iterations: Count,
+        num_samples: Count,
+    ) -> Result<Vec<f64>> {
+        let state = self;
+        let (ping_counter, pong_counter) = (&self.counters.0[0], &self.counters.0[self.offset / 8]);
+
+        crossbeam_utils::thread::scope(|s| {
+            let pong = s.spawn(move |_| state.count(pong_counter, pong_core, clock, num_iterations, num_samples));
+            let ping = s.spawn(move |_| state.count(ping_counter, ping_core, clock, num_iterations, num_samples));
+
+            let pong = pong.join().map_err(Error::thread_panicked)?;
+            let ping = ping.join().map_err(Error::thread_panicked)?;
+            Ok(ping.iter().zip(pong).map(|(a, b)| (a + b) / 2.0).collect())
+        }).map_err(Error::thread_panicked)?
+    }
+}
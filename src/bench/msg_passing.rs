@@ -4,9 +4,20 @@ use std::sync::Barrier;
 use std::sync::atomic::{Ordering, AtomicU64};
 use quanta::Clock;
 
-use super::Count;
+use super::{BenchKind, Count, Requirement};
+use crate::error::{Error, Result};
 use crate::utils;
 
+pub static KIND: BenchKind = BenchKind {
+    name: "msg-passing",
+    description: "One writer and one reader on many cache lines, using the clock",
+    requirements: &[Requirement::InvariantTsc],
+    // This test is not symmetric. We are doing one-way message passing.
+    symmetric: false,
+    new: |config| Box::new(Bench::new(config.num_iterations)),
+    sweep: None,
+};
+
 pub struct Bench {
     barrier: Barrier,
     clocks: Vec<CachePadded<AtomicU64>>,
@@ -23,16 +34,13 @@ impl Bench {
 }
 
 impl super::Bench for Bench {
-    // This test is not symmetric. We are doing one-way message passing.
-    fn is_symmetric(&self) -> bool { false }
-
     fn run(
         &self,
         (recv_core, send_core): (CoreId, CoreId),
         clock: &Clock,
         num_iterations: Count,
         num_samples: Count,
-    ) -> Vec<f64> {
+    ) -> Result<Vec<f64>> {
         let clock_read_overhead_sum = utils::clock_read_overhead_sum(clock, num_iterations);
 
         // A shared time reference
@@ -90,9 +98,9 @@ impl super::Bench for Bench {
                 }
             });
 
-            sender.join().unwrap();
-            receiver.join().unwrap()
-        }).unwrap()
+            sender.join().map_err(Error::thread_panicked)?;
+            receiver.join().map_err(Error::thread_panicked)
+        }).map_err(Error::thread_panicked)?
     }
 }
 
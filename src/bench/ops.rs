@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The operation that writes the shared cache line in the ping-pong benches
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    Store,
+    FetchAdd,
+    Swap,
+    CompareExchange,
+    CompareExchangeWeak,
+}
+
+/// The memory ordering of the operations in the ping-pong benches
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryOrdering {
+    Relaxed,
+    /// Acquire for loads, Release for stores, AcqRel for read-modify-writes
+    AcqRel,
+    SeqCst,
+}
+
+impl Op {
+    pub fn name(self) -> &'static str {
+        match self {
+            Op::Store => "store",
+            Op::FetchAdd => "fetch_add",
+            Op::Swap => "swap",
+            Op::CompareExchange => "compare_exchange",
+            Op::CompareExchangeWeak => "compare_exchange_weak",
+        }
+    }
+
+    /// Replaces `current` with `new` in a line that only this thread writes
+    #[inline(always)]
+    pub fn write(self, line: &AtomicU64, current: u64, new: u64, ordering: MemoryOrdering) {
+        match self {
+            Op::Store => line.store(new, ordering.store()),
+            Op::FetchAdd => { line.fetch_add(new.wrapping_sub(current), ordering.rmw()); }
+            Op::Swap => { line.swap(new, ordering.rmw()); }
+            Op::CompareExchange => { let _ = line.compare_exchange(current, new, ordering.rmw(), ordering.load()); }
+            // The weak version can fail spuriously, even though nobody else writes the line
+            Op::CompareExchangeWeak => while line.compare_exchange_weak(current, new, ordering.rmw(), ordering.load()).is_err() {},
+        }
+    }
+
+    /// Waits for the other thread to write `current` in the shared line, then replaces it with `new`.
+    /// A failed compare-exchange is a read, so compare-exchanges spin on the operation itself.
+    /// The other operations wait with loads before writing.
+    #[inline(always)]
+    pub fn hand_over(self, line: &AtomicU64, current: u64, new: u64, ordering: MemoryOrdering) {
+        match self {
+            Op::CompareExchange => while line.compare_exchange(current, new, ordering.rmw(), ordering.load()).is_err() {},
+            Op::CompareExchangeWeak => while line.compare_exchange_weak(current, new, ordering.rmw(), ordering.load()).is_err() {},
+            _ => {
+                wait_for(line, current, ordering);
+                self.write(line, current, new, ordering);
+            }
+        }
+    }
+}
+
+impl MemoryOrdering {
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryOrdering::Relaxed => "relaxed",
+            MemoryOrdering::AcqRel => "acq_rel",
+            MemoryOrdering::SeqCst => "seq_cst",
+        }
+    }
+
+    pub fn load(self) -> Ordering {
+        match self {
+            MemoryOrdering::Relaxed => Ordering::Relaxed,
+            MemoryOrdering::AcqRel => Ordering::Acquire,
+            MemoryOrdering::SeqCst => Ordering::SeqCst,
+        }
+    }
+
+    pub fn store(self) -> Ordering {
+        match self {
+            MemoryOrdering::Relaxed => Ordering::Relaxed,
+            MemoryOrdering::AcqRel => Ordering::Release,
+            MemoryOrdering::SeqCst => Ordering::SeqCst,
+        }
+    }
+
+    pub fn rmw(self) -> Ordering {
+        match self {
+            MemoryOrdering::Relaxed => Ordering::Relaxed,
+            MemoryOrdering::AcqRel => Ordering::AcqRel,
+            MemoryOrdering::SeqCst => Ordering::SeqCst,
+        }
+    }
+}
+
+/// Spins until the line holds `value`
+#[inline(always)]
+pub fn wait_for(line: &AtomicU64, value: u64, ordering: MemoryOrdering) {
+    while line.load(ordering.load()) != value {}
+}
@@ -0,0 +1,115 @@
+use cache_padded::CachePadded;
+use core_affinity::CoreId;
+use std::sync::Barrier;
+use std::sync::atomic::{AtomicU64, Ordering};
+use quanta::Clock;
+
+use super::{BenchKind, Count, Sweep};
+use crate::error::{Error, Result};
+use crate::utils;
+
+pub const DEFAULT_LINES: [usize; 5] = [1, 2, 4, 8, 16];
+
+const WORDS_PER_LINE: usize = 8;
+
+pub static KIND: BenchKind = BenchKind {
+    name: "payload",
+    description: "Round trip of a message spanning a number of cache lines, and of its acknowledgement",
+    requirements: &[],
+    // The payload only goes from the sender to the receiver. Like msg-passing, (i,j) is core i receiving from core j.
+    symmetric: false,
+    new: |config| Box::new(Bench::new(config.payload_lines)),
+    sweep: Some(Sweep::PayloadLines),
+};
+
+/// Parses a payload size in cache lines
+pub fn parse_lines(s: &str) -> std::result::Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("The payload should span at least 1 cache line".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("Invalid number of cache lines '{}': {}", s, e)),
+    }
+}
+
+type Line = CachePadded<[AtomicU64; WORDS_PER_LINE]>;
+
+pub struct Bench {
+    barrier: CachePadded<Barrier>,
+    payload: Vec<Line>,
+    /// The sequence number of the last message, written by the sender once the payload is written
+    seq: CachePadded<AtomicU64>,
+    /// The sequence number of the last message read, written by the receiver
+    ack: CachePadded<AtomicU64>,
+}
+
+impl Bench {
+    pub fn new(num_lines: usize) -> Self {
+        Self {
+            barrier: CachePadded::new(Barrier::new(2)),
+            payload: (0..num_lines).map(|_| Default::default()).collect(),
+            seq: Default::default(),
+            ack: Default::default(),
+        }
+    }
+}
+
+impl super::Bench for Bench {
+    fn run(
+        &self,
+        (recv_core, send_core): (CoreId, CoreId),
+        clock: &Clock,
+        num_messages: Count,
+        num_samples: Count,
+    ) -> Result<Vec<f64>> {
+        let state = self;
+        // The sequence numbers keep counting from previous runs
+        let base = state.seq.load(Ordering::Relaxed);
+
+        crossbeam_utils::thread::scope(|s| {
+            let receiver = s.spawn(move |_| {
+                core_affinity::set_for_current(recv_core);
+                state.barrier.wait();
+
+                for seq in base+1..=base + (num_messages*num_samples) as u64 {
+                    while state.seq.load(Ordering::Acquire) != seq {}
+                    let mut sum = 0u64;
+                    for word in state.payload.iter().flat_map(|line| line.iter()) {
+                        sum = sum.wrapping_add(word.load(Ordering::Relaxed));
+                    }
+                    utils::black_box(sum);
+                    state.ack.store(seq, Ordering::Release);
+                }
+            });
+
+            let sender = s.spawn(move |_| {
+                core_affinity::set_for_current(send_core);
+
+                let mut results = Vec::with_capacity(num_samples as usize);
+                let mut seq = base;
+
+                state.barrier.wait();
+
+                for _ in 0..num_samples {
+                    let start = clock.raw();
+                    for _ in 0..num_messages {
+                        seq += 1;
+                        for word in state.payload.iter().flat_map(|line| line.iter()) {
+                            word.store(seq, Ordering::Relaxed);
+                        }
+                        state.seq.store(seq, Ordering::Release);
+                        while state.ack.load(Ordering::Acquire) != seq {}
+                    }
+                    let end = clock.raw();
+                    let duration = clock.delta(start, end).as_nanos();
+                    // Not halved like the round trips of the other benches, the acknowledgement is a single line
+                    results.push(duration as f64 / num_messages as f64);
+                }
+
+                results
+            });
+
+            receiver.join().map_err(Error::thread_panicked)?;
+            sender.join().map_err(Error::thread_panicked)
+        }).map_err(Error::thread_panicked)?
+    }
+}
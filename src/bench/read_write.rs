@@ -1,27 +1,52 @@
 use cache_padded::CachePadded;
 use core_affinity::CoreId;
 use std::sync::Barrier;
-use std::sync::atomic::{Ordering, AtomicBool};
+use std::sync::atomic::{Ordering, AtomicU64};
 use quanta::Clock;
 
-use super::Count;
+use super::{BenchKind, Count};
+use super::ops::{wait_for, MemoryOrdering, Op};
+use crate::error::{Error, Result};
+
+pub static KIND: BenchKind = BenchKind {
+    name: "read-write",
+    description: "Single-writer single-reader latency on two shared cache lines",
+    requirements: &[],
+    symmetric: true,
+    new: |config| Box::new(Bench::new(
+        config.op.unwrap_or(Op::Store),
+        config.ordering.unwrap_or(MemoryOrdering::AcqRel),
+    )),
+    sweep: None,
+};
 
 pub struct Bench {
     barrier: CachePadded<Barrier>,
-    owned_by_ping: CachePadded<AtomicBool>,
-    owned_by_pong: CachePadded<AtomicBool>
+    /// Each thread counts the round trips on its own line
+    owned_by_ping: CachePadded<AtomicU64>,
+    owned_by_pong: CachePadded<AtomicU64>,
+    op: Op,
+    ordering: MemoryOrdering,
 }
 
 impl Bench {
-    pub fn new() -> Self {
+    pub fn new(op: Op, ordering: MemoryOrdering) -> Self {
         Self {
             barrier: CachePadded::new(Barrier::new(2)),
             owned_by_ping: Default::default(),
             owned_by_pong: Default::default(),
+            op,
+            ordering,
         }
     }
 }
 
+impl Default for Bench {
+    fn default() -> Self {
+        Self::new(Op::Store, MemoryOrdering::AcqRel)
+    }
+}
+
 impl super::Bench for Bench {
     // Thread 1 writes to cache line 1 and read cache line 2
     // Thread 2 writes to cache line 2 and read cache line 1
@@ -31,20 +56,20 @@ impl super::Bench for Bench {
         clock: &Clock,
         num_round_trips: Count,
         num_samples: Count,
-    ) -> Vec<f64> {
+    ) -> Result<Vec<f64>> {
         let state = self;
+        // The lines keep counting from previous runs
+        let base = state.owned_by_ping.load(Ordering::Relaxed);
 
         crossbeam_utils::thread::scope(|s| {
             let pong = s.spawn(move |_| {
                 core_affinity::set_for_current(pong_core);
                 state.barrier.wait();
-                let mut v = false;
-                for _ in 0..(num_round_trips*num_samples) {
-                    // Acquire -> Release is important to enforce a causal dependency
+                for v in base..base + (num_round_trips*num_samples) as u64 {
+                    // With acq-rel, Acquire -> Release enforces a causal dependency
                     // This has no effect on x86
-                    while state.owned_by_ping.load(Ordering::Acquire) != v {}
-                    state.owned_by_pong.store(!v, Ordering::Release);
-                    v = !v;
+                    wait_for(&state.owned_by_ping, v, state.ordering);
+                    state.op.write(&state.owned_by_pong, v, v+1, state.ordering);
                 }
             });
 
@@ -53,15 +78,15 @@ impl super::Bench for Bench {
 
                 core_affinity::set_for_current(ping_core);
                 state.barrier.wait();
-                let mut v = true;
+                let mut v = base;
                 for _ in 0..num_samples {
                     let start = clock.raw();
                     for _ in 0..num_round_trips {
-                        // Acquire -> Release is important to enforce a causal dependency
+                        // With acq-rel, Acquire -> Release enforces a causal dependency
                         // This has no effect on x86
-                        while state.owned_by_pong.load(Ordering::Acquire) != v {}
-                        state.owned_by_ping.store(v, Ordering::Release);
-                        v = !v;
+                        wait_for(&state.owned_by_pong, v+1, state.ordering);
+                        state.op.write(&state.owned_by_ping, v, v+1, state.ordering);
+                        v += 1;
                     }
                     let end = clock.raw();
                     let duration = clock.delta(start, end).as_nanos();
@@ -70,8 +95,8 @@ impl super::Bench for Bench {
                 results
             });
 
-            pong.join().unwrap();
-            ping.join().unwrap()
-        }).unwrap()
+            pong.join().map_err(Error::thread_panicked)?;
+            ping.join().map_err(Error::thread_panicked)
+        }).map_err(Error::thread_panicked)?
     }
 }
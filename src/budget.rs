@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant};
+
+use crate::bench::{Config, Count};
+use crate::pairs::PairSampling;
+
+/// The time budget doesn't scale the number of iterations per sample below this
+const MIN_ITERATIONS: Count = 100;
+/// The time budget doesn't scale the number of samples per pair below this
+const MIN_SAMPLES: Count = 20;
+
+/// Parses durations like "90s", "10m", "1h30m", or a number of seconds
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration '{}', expected e.g. 90s, 10m or 1h30m", s);
+    let parse = |number: &str| number.parse::<u64>().map_err(|_| invalid());
+
+    let mut secs = 0;
+    let mut number = String::new();
+    for c in s.trim().chars() {
+        let unit = match c {
+            '0'..='9' => { number.push(c); continue; }
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        secs += parse(&number)? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        if secs > 0 {
+            return Err(invalid());
+        }
+        secs = parse(&number)?;
+    }
+
+    match secs {
+        0 => Err(invalid()),
+        secs => Ok(Duration::from_secs(secs)),
+    }
+}
+
+/// Formats a duration as 1h05m, 3m12s or 45s
+pub fn format_duration(d: Duration) -> String {
+    match d.as_secs() {
+        s if s >= 3600 => format!("{}h{:02}m", s / 3600, s % 3600 / 60),
+        s if s >= 60 => format!("{}m{:02}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// How long the measurement of a pair takes, estimated from a first pair
+pub struct Cost {
+    /// Seconds per iteration of a sample
+    per_iteration: f64,
+}
+
+impl Cost {
+    /// `elapsed` is the time it took to measure a pair with `config`, without the time based warmup
+    pub fn new(elapsed: Duration, config: &Config) -> Self {
+        let num_iterations = config.num_iterations as f64 * (config.num_samples + config.warmup_samples) as f64;
+        Self { per_iteration: elapsed.as_secs_f64() / num_iterations }
+    }
+
+    /// Seconds to measure one pair. Adaptive sampling is assumed to stop at `num_samples`.
+    fn pair_time(&self, config: &Config) -> f64 {
+        let passes = config.passes as f64;
+        let num_samples = config.num_samples as f64 + passes * config.warmup_samples as f64;
+        passes * config.warmup_ms as f64 / 1000.0 + self.per_iteration * config.num_iterations as f64 * num_samples
+    }
+
+    /// Seconds to measure all the pairs, running up to `parallel` of them at a time
+    fn total_time(&self, config: &Config, num_pairs: usize) -> f64 {
+        self.pair_time(config) * num_pairs.div_ceil(config.parallel) as f64
+    }
+}
+
+/// The parameters scaled down to measure `num_pairs` pairs within the budget
+pub struct Fit {
+    pub config: Config,
+    /// What was scaled, e.g., "iterations 1000 → 316"
+    pub changes: Vec<String>,
+    pub estimate: Duration,
+}
+
+/// Scales the number of iterations and samples down together, down to a minimum.
+/// If that's not enough, only a random subset of the pairs is measured.
+pub fn fit(config: &Config, cost: &Cost, num_pairs: usize, budget: Duration) -> Fit {
+    let budget = budget.as_secs_f64();
+    let min_iterations = MIN_ITERATIONS.min(config.num_iterations);
+    let min_samples = MIN_SAMPLES.max(config.passes).min(config.num_samples);
+
+    let mut scaled = config.clone();
+    let mut scale = 1.0;
+    while cost.total_time(&scaled, num_pairs) > budget
+        && (scaled.num_iterations > min_iterations || scaled.num_samples > min_samples)
+    {
+        scale *= 0.95;
+        scaled.num_iterations = ((config.num_iterations as f64 * scale) as Count).max(min_iterations);
+        scaled.num_samples = ((config.num_samples as f64 * scale) as Count).max(min_samples);
+    }
+
+    let mut changes = Vec::new();
+    if scaled.num_iterations != config.num_iterations {
+        changes.push(format!("iterations {} → {}", config.num_iterations, scaled.num_iterations));
+    }
+    if scaled.num_samples != config.num_samples {
+        changes.push(format!("samples {} → {}", config.num_samples, scaled.num_samples));
+    }
+
+    let mut num_measured = num_pairs;
+    if cost.total_time(&scaled, num_pairs) > budget && config.pairs != PairSampling::Class {
+        let num_batches = (budget / cost.pair_time(&scaled)) as usize;
+        num_measured = (num_batches * config.parallel).clamp(1, num_pairs);
+        scaled.pairs = PairSampling::Random;
+        scaled.num_pairs = num_measured;
+        changes.push(format!("random subset of {}/{} pairs", num_measured, num_pairs));
+    }
+
+    let estimate = Duration::from_secs_f64(cost.total_time(&scaled, num_measured));
+    Fit { config: scaled, changes, estimate }
+}
+
+/// Estimates the remaining time from the pairs measured so far
+pub struct Eta {
+    start: Instant,
+    num_measured: usize,
+    num_remaining: usize,
+}
+
+impl Eta {
+    pub fn new(num_pairs: usize) -> Self {
+        Self { start: Instant::now(), num_measured: 0, num_remaining: num_pairs }
+    }
+
+    /// Pairs read from a checkpoint take no time, they are not part of the estimate
+    pub fn skipped(&mut self, num_pairs: usize) {
+        self.num_remaining -= num_pairs;
+    }
+
+    pub fn measured(&mut self, num_pairs: usize) {
+        self.num_measured += num_pairs;
+        self.num_remaining -= num_pairs;
+    }
+
+    /// e.g., "ETA 3m12s", or an empty string before the first pair and after the last one
+    pub fn format(&self) -> String {
+        if self.num_measured == 0 || self.num_remaining == 0 {
+            return String::new();
+        }
+        let remaining = self.start.elapsed().mul_f64(self.num_remaining as f64 / self.num_measured as f64);
+        format!("ETA {}", format_duration(remaining))
+    }
+}
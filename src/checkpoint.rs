@@ -0,0 +1,147 @@
+use core_affinity::CoreId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::bench::ops::{MemoryOrdering, Op};
+use crate::bench::{Config, Count};
+use crate::error::{Error, Result};
+use crate::pairs::PairSampling;
+use crate::stats::OutlierFilter;
+
+/// The parameters that must be the same to resume a run
+#[derive(Serialize, Deserialize)]
+pub struct Params {
+    cores: Vec<usize>,
+    num_iterations: Count,
+    num_samples: Count,
+    warmup_samples: Count,
+    warmup_ms: u64,
+    target_stderr: Option<f64>,
+    max_samples: Option<Count>,
+    outliers: OutlierFilter,
+    passes: Count,
+    parallel: usize,
+    pairs: PairSampling,
+    num_pairs: usize,
+    pairs_per_class: usize,
+    op: Option<Op>,
+    ordering: Option<MemoryOrdering>,
+    pub seed: u64,
+}
+
+impl Params {
+    pub fn new(cores: &[CoreId], config: &Config) -> Self {
+        Self {
+            cores: cores.iter().map(|c| c.id).collect(),
+            num_iterations: config.num_iterations,
+            num_samples: config.num_samples,
+            warmup_samples: config.warmup_samples,
+            warmup_ms: config.warmup_ms,
+            target_stderr: config.target_stderr,
+            max_samples: config.max_samples,
+            outliers: config.outliers,
+            passes: config.passes,
+            parallel: config.parallel,
+            pairs: config.pairs,
+            num_pairs: config.num_pairs,
+            pairs_per_class: config.pairs_per_class,
+            op: config.op,
+            ordering: config.ordering,
+            seed: config.seed,
+        }
+    }
+
+    /// The names of the parameters that differ
+    pub fn diff(&self, other: &Params) -> Vec<String> {
+        let (a, b) = (serde_json::to_value(self).unwrap(), serde_json::to_value(other).unwrap());
+        let (a, b) = (a.as_object().unwrap(), b.as_object().unwrap());
+        a.keys().filter(|k| a.get(*k) != b.get(*k)).map(|k| k.replace('_', "-")).collect()
+    }
+}
+
+/// The samples of a pair, measured in one pass
+#[derive(Serialize, Deserialize)]
+struct Record {
+    bench: String,
+    pass: Count,
+    cores: (usize, usize),
+    samples: Vec<f64>,
+    warmup: Vec<f64>,
+}
+
+/// A bench name, pass and pair of core ids
+type Key = (String, Count, (usize, usize));
+
+/// A file with one line of JSON for the parameters, followed by one line per measured pair.
+/// Lines are appended as pairs complete, so an interrupted run loses at most the last pair.
+pub struct Checkpoint {
+    path: PathBuf,
+    file: File,
+    done: HashMap<Key, Record>,
+}
+
+impl Checkpoint {
+    /// Starts a new checkpoint, overwriting the file
+    pub fn create(path: &Path, params: &Params) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", serde_json::to_string(params)?)?;
+        Ok(Self { path: path.to_path_buf(), file, done: HashMap::new() })
+    }
+
+    /// Reads the parameters of a checkpoint
+    pub fn read_params(path: &Path) -> io::Result<Params> {
+        let mut header = String::new();
+        BufReader::new(File::open(path)?).read_line(&mut header)?;
+        Ok(serde_json::from_str(&header)?)
+    }
+
+    /// Loads the pairs measured so far, and appends the next ones to the same file.
+    /// A line truncated by an interrupted write is dropped.
+    pub fn resume(path: &Path) -> io::Result<Self> {
+        let params = Self::read_params(path)?;
+        let records = BufReader::new(File::open(path)?).lines().skip(1)
+            .map_while(|line| line.ok().and_then(|line| serde_json::from_str::<Record>(&line).ok()))
+            .collect::<Vec<_>>();
+
+        // Rewrite the valid lines, so that the next records don't follow a truncated line
+        let tmp = path.with_extension("tmp");
+        let mut checkpoint = Self::create(&tmp, &params)?;
+        for record in records {
+            checkpoint.write(&record)?;
+            checkpoint.done.insert((record.bench.clone(), record.pass, record.cores), record);
+        }
+        fs::rename(&tmp, path)?;
+        checkpoint.path = path.to_path_buf();
+        checkpoint.file = OpenOptions::new().append(true).open(path)?;
+        Ok(checkpoint)
+    }
+
+    pub fn num_pairs(&self) -> usize {
+        self.done.len()
+    }
+
+    /// The samples and warmup samples of a pair measured before, in this pass
+    pub fn get(&self, bench: &str, pass: Count, cores: (CoreId, CoreId)) -> Option<(&[f64], &[f64])> {
+        self.done.get(&(bench.to_string(), pass, (cores.0.id, cores.1.id)))
+            .map(|record| (&record.samples[..], &record.warmup[..]))
+    }
+
+    pub fn record(&mut self, bench: &str, pass: Count, cores: (CoreId, CoreId), samples: &[f64], warmup: &[f64]) -> Result<()> {
+        let record = Record {
+            bench: bench.to_string(),
+            pass,
+            cores: (cores.0.id, cores.1.id),
+            samples: samples.to_vec(),
+            warmup: warmup.to_vec(),
+        };
+        self.write(&record).map_err(Error::io(&self.path))
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        writeln!(self.file, "{}", serde_json::to_string(record)?)?;
+        self.file.flush()
+    }
+}
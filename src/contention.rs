@@ -0,0 +1,127 @@
+use cache_padded::CachePadded;
+use core_affinity::CoreId;
+use quanta::Clock;
+use std::sync::Barrier;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::bench::{Config, Count};
+use crate::error::{Error, Result};
+use crate::utils;
+
+/// The operation that the contending threads do on the shared cache line
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum ContentionOp {
+    /// Increments the counter with a compare-exchange loop, retrying when another thread got there first
+    Cas,
+    /// Increments the counter with a single fetch_add
+    FetchAdd,
+}
+
+impl ContentionOp {
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentionOp::Cas => "cas",
+            ContentionOp::FetchAdd => "fetch_add",
+        }
+    }
+}
+
+/// The samples measured with a number of contending threads
+pub struct ContentionPoint {
+    /// The cores of the threads, one thread per core
+    pub cores: Vec<CoreId>,
+    /// Nanoseconds per operation, as seen by each thread and averaged over the threads, one per sample
+    pub latency: Vec<f64>,
+    /// Operations per microsecond over all the threads, one per sample
+    pub throughput: Vec<f64>,
+}
+
+struct State {
+    barrier: CachePadded<Barrier>,
+    counter: CachePadded<AtomicU64>,
+}
+
+/// Returns (time spent by this thread, time until all the threads are done) per sample
+fn contend(state: &State, op: ContentionOp, core: CoreId, clock: &Clock, num_iterations: Count, num_samples: Count) -> Vec<(u64, u64)> {
+    core_affinity::set_for_current(core);
+    let mut results = Vec::with_capacity(num_samples as usize);
+
+    state.barrier.wait();
+    for _ in 0..num_samples {
+        state.barrier.wait();
+        let start = clock.raw();
+        for _ in 0..num_iterations {
+            match op {
+                ContentionOp::Cas => {
+                    let mut v = state.counter.load(Ordering::Relaxed);
+                    while let Err(current) = state.counter.compare_exchange(v, v+1, Ordering::Relaxed, Ordering::Relaxed) {
+                        v = current;
+                    }
+                }
+                ContentionOp::FetchAdd => {
+                    state.counter.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        let end = clock.raw();
+        state.barrier.wait();
+        let all_done = clock.raw();
+        results.push((clock.delta(start, end).as_nanos() as u64, clock.delta(start, all_done).as_nanos() as u64));
+    }
+    results
+}
+
+/// Measures `cores.len()` threads doing `op` on the same cache line
+fn measure_point(cores: &[CoreId], op: ContentionOp, clock: &Clock, config: &Config) -> Result<ContentionPoint> {
+    let state = State {
+        barrier: CachePadded::new(Barrier::new(cores.len())),
+        counter: Default::default(),
+    };
+    let num_warmup = config.warmup_samples as usize;
+    let num_samples = config.warmup_samples + config.num_samples;
+
+    let state = &state;
+    let threads = crossbeam_utils::thread::scope(|s| {
+        let handles = cores.iter()
+            .map(|&core| s.spawn(move |_| contend(state, op, core, clock, config.num_iterations, num_samples)))
+            .collect::<Vec<_>>();
+        handles.into_iter().map(|h| h.join().map_err(Error::thread_panicked)).collect::<Result<Vec<_>>>()
+    }).map_err(Error::thread_panicked)??;
+
+    let num_threads = cores.len() as f64;
+    let num_iterations = config.num_iterations as f64;
+    let (latency, throughput) = (num_warmup..num_samples as usize)
+        .map(|s| {
+            let busy = threads.iter().map(|t| t[s].0 as f64).sum::<f64>() / num_threads;
+            // From the first thread to start until all the threads are done
+            let elapsed = threads.iter().map(|t| t[s].1).max().unwrap_or_default() as f64;
+            (busy / num_iterations, num_threads * num_iterations / elapsed * 1000.0)
+        })
+        .unzip();
+
+    Ok(ContentionPoint {
+        cores: cores.to_vec(),
+        latency,
+        throughput,
+    })
+}
+
+/// Measures K threads contending on one cache line, for K from 2 to the number of cores.
+/// The K threads run on the first K cores.
+pub fn measure_contention(
+    cores: &[CoreId],
+    op: ContentionOp,
+    clock: &Clock,
+    config: &Config,
+    mut on_point: impl FnMut(&ContentionPoint),
+) -> Result<Vec<ContentionPoint>> {
+    utils::check_cores(cores)?;
+
+    (2..=cores.len())
+        .map(|k| {
+            let point = measure_point(&cores[..k], op, clock, config)?;
+            on_point(&point);
+            Ok(point)
+        })
+        .collect()
+}
@@ -0,0 +1,266 @@
+use core_affinity::CoreId;
+use std::str::FromStr;
+
+use crate::topology::{CpuTopology, Topology};
+
+/// Parses the Linux cpulist format used by taskset and sysfs, e.g., "0-3,8,10-15:2"
+pub fn parse_cpulist(s: &str) -> Result<Vec<usize>, String> {
+    let mut cpus = Vec::new();
+    for item in s.trim().split(',').filter(|item| !item.is_empty()) {
+        let (range, stride) = match item.split_once(':') {
+            Some((range, stride)) => (range, parse_id(stride)?),
+            None => (item, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (parse_id(first)?, parse_id(last)?),
+            None => (parse_id(range)?, parse_id(range)?),
+        };
+        if first > last || stride == 0 {
+            return Err(format!("Invalid range '{}'", item));
+        }
+        cpus.extend((first..=last).step_by(stride));
+    }
+    Ok(cpus)
+}
+
+fn parse_id(s: &str) -> Result<usize, String> {
+    s.trim().parse().map_err(|_| format!("Invalid core id '{}'", s))
+}
+
+/// A set of cores, as written on the command line
+#[derive(Clone, Debug)]
+enum CoreSet {
+    /// Ids in the cpulist format
+    Ids(Vec<usize>),
+    /// @nodeN: the cores of a NUMA node
+    Node(usize),
+    /// @socketN: the cores of a physical package
+    Socket(usize),
+}
+
+impl CoreSet {
+    fn resolve(&self, topology: Option<&Topology>) -> Result<Vec<usize>, String> {
+        let (kind, ids) = match (self, topology) {
+            (CoreSet::Ids(ids), _) => return Ok(ids.clone()),
+            (_, None) => return Err("The cpu topology is needed for @node and @socket, but is not available".to_string()),
+            (CoreSet::Node(node), Some(topology)) => (format!("NUMA node {}", node), topology.node_cpus(*node)),
+            (CoreSet::Socket(socket), Some(topology)) => (format!("Socket {}", socket), topology.socket_cpus(*socket)),
+        };
+        if ids.is_empty() {
+            return Err(format!("{} not found", kind));
+        }
+        Ok(ids)
+    }
+}
+
+impl FromStr for CoreSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if let Some(node) = s.strip_prefix("@node") {
+            Ok(CoreSet::Node(parse_id(node)?))
+        } else if let Some(socket) = s.strip_prefix("@socket") {
+            Ok(CoreSet::Socket(parse_id(socket)?))
+        } else if s.starts_with('@') {
+            Err(format!("Unknown core group '{}', expected @nodeN or @socketN", s))
+        } else {
+            Ok(CoreSet::Ids(parse_cpulist(s)?))
+        }
+    }
+}
+
+/// The cores selected with --cores, e.g., "0-15,64-79,^3" or "@socket1,^@node3"
+#[derive(Clone, Debug, Default)]
+pub struct CoreSelection {
+    include: Vec<CoreSet>,
+    exclude: Vec<CoreSet>,
+}
+
+impl FromStr for CoreSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut selection = Self::default();
+        for item in s.split(',').filter(|item| !item.is_empty()) {
+            match item.strip_prefix('^') {
+                Some(item) => selection.exclude.push(item.parse()?),
+                None => selection.include.push(item.parse()?),
+            }
+        }
+        Ok(selection)
+    }
+}
+
+impl CoreSelection {
+    /// Returns the selected cores in the given order. When only exclusions are given,
+    /// we start from all the available cores. All unknown cores are reported at once.
+    pub fn resolve(&self, available: &[CoreId], topology: Option<&Topology>) -> Result<Vec<CoreId>, String> {
+        let mut ids = Vec::new();
+        for set in &self.include {
+            for id in set.resolve(topology)? {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+
+        let missing = ids.iter()
+            .filter(|&&id| !available.iter().any(|c| c.id == id))
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            let available = available.iter().map(|c| c.id.to_string()).collect::<Vec<_>>();
+            return Err(format!("Cores {} not found. Available: {}", missing.join(","), available.join(",")));
+        }
+
+        if self.include.is_empty() {
+            ids = available.iter().map(|c| c.id).collect();
+        }
+
+        for set in &self.exclude {
+            let excluded = set.resolve(topology)?;
+            ids.retain(|id| !excluded.contains(id));
+        }
+
+        Ok(ids.into_iter().map(|id| CoreId { id }).collect())
+    }
+}
+
+/// The order of the cores in the matrix
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum CoreOrder {
+    /// The order given with --cores, or the enumeration order of the system
+    Given,
+    /// Sorted by core id
+    Id,
+    /// Grouped by socket, die, L3 domain, cluster and physical core
+    Topology,
+    /// Reordered after measurement, so that cores with low latencies between them are next to each other
+    Latency,
+}
+
+impl CoreOrder {
+    /// Sorts the cores before measurement. The latency order is applied after measurement.
+    pub fn sort(self, cores: &mut [CoreId], topology: Option<&Topology>) {
+        match (self, topology) {
+            (CoreOrder::Given | CoreOrder::Latency, _) => {}
+            (CoreOrder::Id, _) | (CoreOrder::Topology, None) => cores.sort_by_key(|c| c.id),
+            (CoreOrder::Topology, Some(topology)) => cores.sort_by_key(|c| {
+                let t = topology.get(c.id);
+                let key = |f: fn(&CpuTopology) -> Option<usize>| t.and_then(f);
+                (key(|t| t.package), key(|t| t.die), key(|t| t.l3), key(|t| t.cluster), key(|t| t.l2), key(|t| t.core), c.id)
+            }),
+        }
+    }
+}
+
+/// How to handle pairs of hyper-threads of the same physical core
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum SmtMode {
+    /// Measure all pairs
+    Include,
+    /// Keep one logical cpu per physical core
+    Exclude,
+    /// Only measure pairs of hyper-threads of the same physical core
+    Only,
+}
+
+impl SmtMode {
+    /// Removes the cores that can't be part of a measured pair
+    pub fn filter_cores(self, cores: &mut Vec<CoreId>, topology: Option<&Topology>) -> Result<(), String> {
+        let topology = match (self, topology) {
+            (SmtMode::Include, _) => return Ok(()),
+            (_, Some(topology)) => topology,
+            (_, None) => return Err("The cpu topology is needed for --smt, but is not available".to_string()),
+        };
+
+        match self {
+            SmtMode::Include => {}
+            SmtMode::Exclude => {
+                let mut kept: Vec<CoreId> = Vec::new();
+                for &core in cores.iter() {
+                    if !kept.iter().any(|k| topology.are_smt_siblings(k.id, core.id)) {
+                        kept.push(core);
+                    }
+                }
+                *cores = kept;
+            }
+            SmtMode::Only => {
+                let all = cores.clone();
+                cores.retain(|c| all.iter().any(|o| topology.are_smt_siblings(c.id, o.id)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn selects_pair(self, a: CoreId, b: CoreId, topology: Option<&Topology>) -> bool {
+        match (self, topology) {
+            (SmtMode::Only, Some(topology)) => topology.are_smt_siblings(a.id, b.id),
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cores(ids: impl IntoIterator<Item = usize>) -> Vec<CoreId> {
+        ids.into_iter().map(|id| CoreId { id }).collect()
+    }
+
+    fn resolve(selection: &str, available: &[CoreId]) -> Result<Vec<usize>, String> {
+        let selection = selection.parse::<CoreSelection>()?;
+        Ok(selection.resolve(available, None)?.iter().map(|c| c.id).collect())
+    }
+
+    #[test]
+    fn cpulist_ranges() {
+        assert_eq!(parse_cpulist("0-3,8"), Ok(vec![0, 1, 2, 3, 8]));
+        assert_eq!(parse_cpulist(" 5 \n"), Ok(vec![5]));
+        assert_eq!(parse_cpulist(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn cpulist_strides() {
+        assert_eq!(parse_cpulist("0-10:4"), Ok(vec![0, 4, 8]));
+        assert_eq!(parse_cpulist("1-3:1,10-15:2"), Ok(vec![1, 2, 3, 10, 12, 14]));
+    }
+
+    #[test]
+    fn cpulist_errors() {
+        assert!(parse_cpulist("3-1").is_err());
+        assert!(parse_cpulist("0-3:0").is_err());
+        assert!(parse_cpulist("a").is_err());
+        assert!(parse_cpulist("1-").is_err());
+    }
+
+    #[test]
+    fn exclusions() {
+        let available = cores(0..8);
+        assert_eq!(resolve("0-5,^2-3", &available), Ok(vec![0, 1, 4, 5]));
+        // The order of --cores is kept, and duplicates are dropped
+        assert_eq!(resolve("6,1,6,^0", &available), Ok(vec![6, 1]));
+    }
+
+    #[test]
+    fn exclusions_only() {
+        let available = cores(0..8);
+        assert_eq!(resolve("^0,^4-7", &available), Ok(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn groups_need_the_topology() {
+        let available = cores(0..8);
+        assert!(resolve("@node0", &available).is_err());
+        assert!(resolve("^@socket1", &available).is_err());
+        assert!("@cluster0".parse::<CoreSelection>().is_err());
+    }
+
+    #[test]
+    fn unknown_cores_are_reported_at_once() {
+        let available = cores(0..4);
+        let error = resolve("2-5,9", &available).unwrap_err();
+        assert!(error.starts_with("Cores 4,5,9 not found"), "{}", error);
+    }
+}
@@ -0,0 +1,69 @@
+use std::any::Any;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Why a run failed. Each kind of failure exits with its own code, see `exit_code`.
+#[derive(Debug)]
+pub enum Error {
+    /// The command line arguments or the measurement parameters are invalid
+    InvalidArgs(String),
+    /// The cores can't be listed, or a thread can't be pinned to a core
+    Affinity(String),
+    /// The clock can't be used to time a benchmark on this machine
+    Clock(String),
+    /// A thread of a benchmark panicked
+    BenchThread(String),
+    /// A file can't be read or written
+    Io { path: PathBuf, source: io::Error },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// 2 for invalid arguments, like clap. 3 and 4 mean that the machine is not supported,
+    /// 5 is a bug, and 6 is a problem with the files.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidArgs(_) => 2,
+            Error::Affinity(_) => 3,
+            Error::Clock(_) => 4,
+            Error::BenchThread(_) => 5,
+            Error::Io { .. } => 6,
+        }
+    }
+
+    /// The error of a thread that panicked, from the payload returned by `join()`
+    pub fn thread_panicked(payload: Box<dyn Any + Send>) -> Self {
+        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Error::BenchThread(message)
+    }
+
+    pub fn io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::Io { path, source }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidArgs(message) => write!(f, "{}", message),
+            Error::Affinity(message) => write!(f, "{}", message),
+            Error::Clock(message) => write!(f, "{}", message),
+            Error::BenchThread(message) => write!(f, "A benchmark thread panicked: {}", message),
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! Measures the latency between CPU cores, by bouncing cache lines between pairs of threads
+//! pinned to each core.
+//!
+//! `run_matrix` measures every pair of the given cores and returns the samples, without printing anything.
+
+pub mod analysis;
+pub mod bench;
+pub mod budget;
+pub mod checkpoint;
+pub mod contention;
+pub mod cpulist;
+pub mod error;
+pub mod output;
+pub mod pairs;
+pub mod stats;
+pub mod topology;
+pub mod utils;
+
+pub use bench::{measure_matrix, run_matrix, Bench, BenchKind, Config, LatencyMatrix, BENCHES};
+pub use error::{Error, Result};
@@ -1,14 +1,25 @@
-mod bench;
-mod utils;
+mod report;
 
-use bench::Count;
+use core_to_core_latency::{budget, checkpoint, output, topology, utils};
+use core_to_core_latency::bench::false_sharing;
+use core_to_core_latency::bench::ops::{MemoryOrdering, Op};
+use core_to_core_latency::bench::payload;
+use core_to_core_latency::bench::{BenchKind, BENCHES, Config, Sweep, Count, DEFAULT_NUM_ITERATIONS_PER_SAMPLE, DEFAULT_NUM_SAMPLES};
+use core_to_core_latency::checkpoint::Checkpoint;
+use core_to_core_latency::contention::ContentionOp;
+use core_to_core_latency::error::{Error, Result};
+use core_to_core_latency::cpulist::{CoreOrder, CoreSelection, SmtMode};
+use core_to_core_latency::output::CsvFormat;
+use core_to_core_latency::pairs::PairSampling;
+use core_to_core_latency::stats::{OutlierFilter, Stat};
+use core_to_core_latency::topology::Topology;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use clap::builder::RangedU64ValueParser;
 use clap::Parser;
 use quanta::Clock;
-use crate::bench::run_bench;
-
-const DEFAULT_NUM_SAMPLES: Count = 300;
-const DEFAULT_NUM_ITERATIONS_PER_SAMPLE: Count = 1000;
+use crate::report::{print_sweep_curve, run_bench, run_contention};
 
 #[derive(Clone)]
 #[derive(clap::Parser)]
@@ -21,67 +32,380 @@ pub struct CliArgs {
     #[clap(default_value_t = DEFAULT_NUM_SAMPLES, value_parser)]
     num_samples: Count,
 
-    /// Outputs the mean latencies in CSV format on stdout
+    /// The number of samples discarded before measuring each pair
+    #[clap(long, default_value_t = 1, value_parser)]
+    warmup_samples: Count,
+
+    /// Warms up each pair for this many milliseconds before the warmup samples
+    #[clap(long, default_value_t = 0, value_parser)]
+    warmup_ms: u64,
+
+    /// Keeps sampling each pair in batches of <NUM_SAMPLES> until the standard deviation
+    /// of its mean falls below this many nanoseconds, or <MAX_SAMPLES> is reached
+    #[clap(long, value_parser)]
+    target_stderr: Option<f64>,
+
+    /// The maximum number of samples per pair with --target-stderr. Defaults to 10x <NUM_SAMPLES>
+    #[clap(long, value_parser, requires = "target-stderr")]
+    max_samples: Option<Count>,
+
+    /// Computes bootstrap confidence intervals of the means with this many resamples, e.g., 1000
+    #[clap(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    bootstrap: Option<usize>,
+
+    /// Outputs the latencies in CSV format on stdout
     #[clap(long, value_parser)]
     csv: bool,
 
-    /// Select which benchmark to run, in a comma delimited list, e.g., '1,3' {n}
-    /// 1: CAS latency on a single shared cache line. {n}
-    /// 2: Single-writer single-reader latency on two shared cache lines. {n}
-    /// 3: One writer and one reader on many cache line, using the clock. {n}
-    #[clap(short, long, default_value="1", require_delimiter=true, value_delimiter=',', value_parser)]
-    bench: Vec<usize>,
+    /// The statistic of each pair shown in the matrix and the CSV output
+    #[clap(long, value_enum, default_value_t = Stat::Mean)]
+    stat: Stat,
+
+    /// Rejects outliers in the samples of each pair before computing statistics {n}
+    /// none: keep all samples {n}
+    /// mad: drop samples further than 3 scaled median absolute deviations from the median {n}
+    /// iqr: drop samples further than 1.5 interquartile ranges outside of the quartiles {n}
+    #[clap(long, value_enum, default_value_t = OutlierFilter::None)]
+    outliers: OutlierFilter,
+
+    /// The format of the CSV output {n}
+    /// bare: the matrix of latencies {n}
+    /// labelled: the matrix of latencies, with core ids as the header row and first column {n}
+    /// long: one row per sample, as bench,core_i,core_j,sample,latency_ns {n}
+    #[clap(long, value_enum, default_value_t = CsvFormat::Bare, requires = "csv")]
+    csv_format: CsvFormat,
+
+    /// Writes the CSV output of each benchmark to <OUTPUT_DIR>/<bench>.csv instead of stdout
+    #[clap(long, value_parser, requires = "csv")]
+    output_dir: Option<PathBuf>,
+
+    /// Writes all the samples, core ids and summary statistics in JSON format to the given file
+    #[clap(long, value_parser)]
+    json: Option<PathBuf>,
+
+    /// Select which benchmarks to run by name, in a comma delimited list, e.g., 'cas,msg-passing'. {n}
+    /// The numbers of older versions, e.g., '1,3', still work. See --list-benches.
+    #[clap(short, long, default_value="cas", require_delimiter=true, value_delimiter=',', value_parser = BenchKind::find)]
+    bench: Vec<&'static BenchKind>,
+
+    /// Lists the benchmarks with their descriptions and requirements, and exits
+    #[clap(long, value_parser)]
+    list_benches: bool,
+
+    /// The operation that writes the cache lines in the cas, read-write and false-sharing benches. {n}
+    /// By default, cas spins on compare-exchange, and the others use plain stores. {n}
+    /// Compare-exchanges spin on the operation itself, the other operations wait with loads first.
+    #[clap(long, value_enum)]
+    op: Option<Op>,
+
+    /// The memory ordering of the cas, read-write and false-sharing benches. {n}
+    /// By default, read-write is acq-rel and the others are relaxed. {n}
+    /// relaxed: Relaxed everywhere {n}
+    /// acq-rel: Acquire loads, Release stores and AcqRel read-modify-writes {n}
+    /// seq-cst: SeqCst everywhere {n}
+    #[clap(long, value_enum)]
+    ordering: Option<MemoryOrdering>,
+
+    /// The distance in bytes between the counters of the false-sharing bench, a multiple of 8. {n}
+    /// Below 64, the counters share a cache line. 64 puts them on adjacent lines, which the adjacent line
+    /// prefetcher of Intel CPUs fetches together, and 128 on separate pairs of lines. {n}
+    /// In a comma delimited list, the bench is run once per offset and compared to the largest offset.
+    #[clap(long, default_values_t = false_sharing::DEFAULT_OFFSETS, require_delimiter=true, value_delimiter=',', value_parser = false_sharing::parse_offset)]
+    false_sharing_offset: Vec<usize>,
+
+    /// The payload sizes of the payload bench, in cache lines, in a comma delimited list.
+    /// The bench is run once per size, and compared to the smallest size.
+    #[clap(long, default_values_t = payload::DEFAULT_LINES, require_delimiter=true, value_delimiter=',', value_parser = payload::parse_lines)]
+    payload_lines: Vec<usize>,
+
+    /// Instead of the latency matrices, measures K threads contending on a single cache line,
+    /// for K from 2 to the number of cores. The K threads run on the first K cores of --cores, in the --order. {n}
+    /// cas: increment with a compare-exchange loop {n}
+    /// fetch-add: increment with fetch_add {n}
+    #[clap(long, value_enum, conflicts_with_all = &["bench", "checkpoint", "resume", "time-budget", "json"])]
+    contention: Option<ContentionOp>,
+
+    /// Specify the cores by id that should be used, in the cpulist format of taskset, e.g., '0-15,64-79'. {n}
+    /// A '^' prefix excludes cores, e.g., '^3'. {n}
+    /// @nodeN and @socketN select the cores of a NUMA node or a socket, e.g., '@socket1,^@node3'. {n}
+    /// By default all cores are used.
+    #[clap(short, long, value_parser)]
+    cores: Option<CoreSelection>,
+
+    /// How to handle hyper-threads of the same physical core {n}
+    /// include: measure all pairs {n}
+    /// exclude: keep one logical cpu per physical core {n}
+    /// only: only measure pairs of hyper-threads of the same physical core {n}
+    #[clap(long, value_enum, default_value_t = SmtMode::Include)]
+    smt: SmtMode,
+
+    /// Which pairs of cores to measure {n}
+    /// all: all pairs {n}
+    /// random: a random subset of <NUM_PAIRS> pairs {n}
+    /// class: <PAIRS_PER_CLASS> random pairs per topology relationship, e.g., shared L3 or cross socket {n}
+    #[clap(long, value_enum, default_value_t = PairSampling::All)]
+    pairs: PairSampling,
+
+    /// The number of pairs measured with --pairs random
+    #[clap(long, default_value_t = 100, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    num_pairs: usize,
+
+    /// The number of pairs measured per topology relationship with --pairs class
+    #[clap(long, default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pairs_per_class: usize,
+
+    /// Takes the samples of each pair in this many passes over all the pairs, in a shuffled order,
+    /// so that thermal and frequency drift is spread across pairs
+    #[clap(long, default_value_t = 1, value_parser, conflicts_with = "target-stderr")]
+    passes: Count,
+
+    /// The seed of the random pair order, pair sampling and bootstrap. Random by default
+    #[clap(long, value_parser)]
+    seed: Option<u64>,
+
+    /// Appends each measured pair to this file, so that an interrupted run can be resumed
+    #[clap(long, value_parser, conflicts_with = "resume")]
+    checkpoint: Option<PathBuf>,
+
+    /// Resumes the run saved in this checkpoint file, skipping the pairs already measured.
+    /// The cores and measurement parameters must be the same
+    #[clap(long, value_parser)]
+    resume: Option<PathBuf>,
+
+    /// Scales the iterations and samples down, and measures a random subset of the pairs if needed,
+    /// so that the run takes about this long, e.g., 90s, 10m or 1h30m.
+    /// The time is estimated from a first pair, and split evenly between the benches
+    #[clap(long, value_parser = budget::parse_duration, conflicts_with_all = &["checkpoint", "resume"])]
+    time_budget: Option<Duration>,
+
+    /// Measures up to this many pairs at the same time, on disjoint cores.
+    /// Pairs in different L3 domains and sockets are preferred.
+    #[clap(long, default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    parallel: usize,
+
+    /// The order of the cores in the matrix {n}
+    /// given: the order of --cores, or the enumeration order of the system {n}
+    /// id: sorted by core id {n}
+    /// topology: grouped by socket, die, L3 domain, cluster and physical core {n}
+    /// latency: reordered after measurement by hierarchical clustering of the latencies {n}
+    #[clap(long, value_enum, default_value_t = CoreOrder::Given)]
+    order: CoreOrder,
+
+    /// Where to read the cpu topology from. Useful for testing with a copy of another machine's sysfs.
+    #[clap(long, default_value = topology::DEFAULT_SYSFS_ROOT, value_parser)]
+    sysfs_root: PathBuf,
+}
+
+impl CliArgs {
+    /// The measurement parameters of a bench. The time budget is split evenly between the runs.
+    fn config(&self, bench: &'static BenchKind) -> Config {
+        Config {
+            bench,
+            num_iterations: self.num_iterations,
+            num_samples: self.num_samples,
+            warmup_samples: self.warmup_samples,
+            warmup_ms: self.warmup_ms,
+            target_stderr: self.target_stderr,
+            max_samples: self.max_samples,
+            outliers: self.outliers,
+            passes: self.passes,
+            parallel: self.parallel,
+            pairs: self.pairs,
+            num_pairs: self.num_pairs,
+            pairs_per_class: self.pairs_per_class,
+            op: self.op,
+            ordering: self.ordering,
+            false_sharing_offset: self.false_sharing_offset[0],
+            payload_lines: self.payload_lines[0],
+            smt: self.smt,
+            seed: self.seed.unwrap_or_default(),
+            time_budget: self.time_budget.map(|budget| budget / self.num_runs() as u32),
+        }
+    }
+
+    /// The values of a sweep
+    fn sweep_values(&self, sweep: Sweep) -> &[usize] {
+        match sweep {
+            Sweep::PayloadLines => &self.payload_lines,
+            Sweep::FalseSharingOffset => &self.false_sharing_offset,
+        }
+    }
+
+    /// The runs of a bench, one per value for the benches with a sweep
+    fn configs(&self, bench: &'static BenchKind) -> Vec<Config> {
+        match bench.sweep {
+            Some(sweep) => self.sweep_values(sweep).iter().map(|&v| sweep.with(self.config(bench), v)).collect(),
+            None => vec![self.config(bench)],
+        }
+    }
 
-    /// Specify the cores by id that should be used, comma delimited. By default all cores are used.
-    #[clap(short, long, require_delimiter=true, value_delimiter=',', value_parser)]
-    cores: Vec<usize>,
+    /// The number of runs over all the benches, one per value for the benches with a sweep
+    fn num_runs(&self) -> usize {
+        self.bench.iter().map(|bench| bench.sweep.map_or(1, |sweep| self.sweep_values(sweep).len())).sum()
+    }
 }
 
 fn main() {
-    let args = CliArgs::parse();
+    if let Err(e) = run(CliArgs::parse()) {
+        eprintln!("{} {}", ansi_term::Color::Red.bold().paint("error:"), e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn list_benches() {
+    let width = BENCHES.iter().map(|kind| kind.name.len()).max().unwrap_or_default();
+    for kind in BENCHES {
+        let requirements = kind.requirements.iter().map(|r| r.name()).collect::<Vec<_>>();
+        match requirements.is_empty() {
+            true => println!("{: <width$}  {}", kind.name, kind.description, width = width),
+            false => println!("{: <width$}  {} (needs {})", kind.name, kind.description, requirements.join(", "), width = width),
+        }
+    }
+}
+
+fn run(mut args: CliArgs) -> Result<()> {
+    if args.list_benches {
+        list_benches();
+        return Ok(());
+    }
+    let resumed = match &args.resume {
+        Some(path) => Some(Checkpoint::read_params(path).map_err(Error::io(path))?),
+        None => None,
+    };
+    // Resume with the same pair order and pair sampling
+    if args.seed.is_none() {
+        args.seed = resumed.as_ref().map(|params| params.seed);
+    }
+    let seed = *args.seed.get_or_insert_with(rand::random);
 
-    let cores = core_affinity::get_core_ids().expect("get_core_ids() failed");
+    let cores = core_affinity::get_core_ids()
+        .ok_or_else(|| Error::Affinity("Failed to list the cores this process can run on".to_string()))?;
+    // The topology is only available on Linux
+    let topology = Topology::from_sysfs(&args.sysfs_root).ok();
 
-    let cores = if !args.cores.is_empty() {
-        args.cores.iter().copied()
-            .map(|cid| *cores.iter().find(|c| c.id == cid)
-                .unwrap_or_else(||panic!("Core {} not found. Available: {:?}", cid, &cores)))
-            .collect()
-    } else {
-        cores
+    let mut cores = match &args.cores {
+        Some(selection) => selection.resolve(&cores, topology.as_ref()).map_err(Error::InvalidArgs)?,
+        None => cores,
+    };
+    args.smt.filter_cores(&mut cores, topology.as_ref()).map_err(Error::InvalidArgs)?;
+    args.order.sort(&mut cores, topology.as_ref());
+    if args.passes == 0 || args.passes > args.num_samples {
+        return Err(Error::InvalidArgs(format!("--passes should be between 1 and {}", args.num_samples)));
+    }
+
+    let params = checkpoint::Params::new(&cores, &args.config(args.bench[0]));
+    let mut checkpoint = match (&args.resume, &args.checkpoint) {
+        (Some(path), _) => {
+            let differ = resumed.as_ref().map(|resumed| resumed.diff(&params)).unwrap_or_default();
+            if !differ.is_empty() {
+                return Err(Error::InvalidArgs(format!(
+                    "Cannot resume {}, these parameters differ: {}", path.display(), differ.join(", "))));
+            }
+            Some(Checkpoint::resume(path).map_err(Error::io(path))?)
+        }
+        (None, Some(path)) => Some(Checkpoint::create(path, &params).map_err(Error::io(path))?),
+        (None, None) => None,
     };
 
     utils::show_cpuid_info();
     eprintln!("Num cores: {}", cores.len());
     eprintln!("Num iterations per samples: {}", args.num_iterations);
     eprintln!("Num samples: {}", args.num_samples);
+    eprintln!("Seed: {}", seed);
+    if let Some(op) = args.op {
+        eprintln!("Op: {}", op.name());
+    }
+    if let Some(ordering) = args.ordering {
+        eprintln!("Memory ordering: {}", ordering.name());
+    }
+    if let (Some(path), Some(checkpoint)) = (&args.resume, &checkpoint) {
+        eprintln!("Resuming {} measured pairs from {}", checkpoint.num_pairs(), path.display());
+    }
     #[cfg(target_os = "macos")]
     eprintln!("{}", ansi_term::Color::Red.bold().paint("WARN macOS may ignore thread-CPU affinity (we can't select a CPU to run on). Results may be inaccurate"));
 
     let clock = Arc::new(Clock::new());
 
-    for b in &args.bench {
-        match b {
-            1 => {
-                eprintln!();
-                eprintln!("1) CAS latency on a single shared cache line");
-                eprintln!();
-                run_bench(&cores, &clock, &args, bench::cas::Bench::new());
-            }
-            2 => {
-                eprintln!();
-                eprintln!("2) Single-writer single-reader latency on two shared cache lines");
-                eprintln!();
-                run_bench(&cores, &clock, &args, bench::read_write::Bench::new());
+    if let Some(op) = args.contention {
+        eprintln!();
+        eprintln!("contention: {} by K threads on a single shared cache line", op.name());
+        eprintln!();
+        let points = run_contention(&cores, &clock, &args, &args.config(args.bench[0]), op)?;
+
+        if args.csv {
+            if let Some(dir) = &args.output_dir {
+                let path = dir.join("contention.csv");
+                output::write_file(&path, |out| output::write_contention_csv(out, args.csv_format, args.stat, &points))
+                    .map_err(Error::io(&path))?;
+                eprintln!("    Wrote {}", path.display());
+            } else {
+                output::write_contention_csv(&mut std::io::stdout().lock(), args.csv_format, args.stat, &points)
+                    .map_err(Error::io("stdout"))?;
             }
-            3 => {
-                utils::assert_rdtsc_usable(&clock);
-                eprintln!();
-                eprintln!("3) Message passing. One writer and one reader on many cache line");
-                eprintln!();
-                run_bench(&cores, &clock, &args, bench::msg_passing::Bench::new(args.num_iterations));
+        }
+        return Ok(());
+    }
+
+    let mut benches = Vec::new();
+    for config in args.bench.iter().flat_map(|&kind| args.configs(kind)) {
+        eprintln!();
+        eprintln!("{}: {}", config.name(), config.bench.description);
+        eprintln!();
+        let results = run_bench(&cores, topology.as_ref(), &clock, &args, &config, checkpoint.as_mut())?;
+
+        if args.csv {
+            if let Some(dir) = &args.output_dir {
+                let path = dir.join(format!("{}.csv", results.name));
+                output::write_csv_file(&path, args.csv_format, args.stat, &results).map_err(Error::io(&path))?;
+                eprintln!("    Wrote {}", path.display());
+            } else {
+                output::write_csv(&mut std::io::stdout().lock(), args.csv_format, args.stat, &results)
+                    .map_err(Error::io("stdout"))?;
             }
-            _ => panic!("--bench should be 1, 2 or 3"),
         }
+
+        benches.push(results);
+    }
+
+    for &kind in &args.bench {
+        if let Some(sweep) = kind.sweep {
+            let runs = benches.iter().filter(|b| b.config.bench.name == kind.name).collect::<Vec<_>>();
+            print_sweep_curve(kind, sweep, &runs, args.stat);
+        }
+    }
+
+    if let Some(path) = &args.json {
+        output::write_json(path, &cores, topology.as_ref(), &args.config(args.bench[0]), args.stat, &benches)
+            .map_err(Error::io(path))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget_per_run(args: &[&str]) -> Vec<Option<Duration>> {
+        let args = CliArgs::parse_from(args);
+        args.bench.iter().flat_map(|&bench| args.configs(bench)).map(|config| config.time_budget).collect()
+    }
+
+    #[test]
+    fn time_budget_is_split_between_benches() {
+        let budgets = budget_per_run(&["c2c", "--time-budget", "60s", "--bench", "cas,read-write"]);
+        assert_eq!(budgets, vec![Some(Duration::from_secs(30)); 2]);
+    }
+
+    #[test]
+    fn time_budget_is_split_between_payload_sizes() {
+        let budgets = budget_per_run(&["c2c", "--time-budget", "60s", "--bench", "cas,payload", "--payload-lines", "1,4,16"]);
+        assert_eq!(budgets, vec![Some(Duration::from_secs(15)); 4]);
+    }
+
+    #[test]
+    fn false_sharing_runs_once_per_offset() {
+        let args = CliArgs::parse_from(["c2c", "--bench", "false-sharing", "--false-sharing-offset", "8,128"]);
+        let names = args.configs(args.bench[0]).iter().map(|config| config.name()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["false-sharing-8", "false-sharing-128"]);
     }
 }
@@ -0,0 +1,298 @@
+use core_affinity::CoreId;
+use ndarray::{Array2, ArrayView1};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::bench::ops::{MemoryOrdering, Op};
+use crate::bench::{Config, Count, LatencyMatrix, Results};
+use crate::contention::ContentionPoint;
+use crate::stats::{self, Bootstrap, Stat, Summary};
+use crate::topology::{CpuTopology, Topology};
+use crate::utils;
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CsvFormat {
+    /// The matrix of latencies, without any labels
+    Bare,
+    /// The matrix of latencies, with a header row and a leading column of core ids
+    Labelled,
+    /// One row per sample: bench,core_i,core_j,sample,latency_ns
+    Long,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    cpu: Option<String>,
+    /// As requested. Each bench has the values it was measured with, once scaled down to the time budget.
+    num_iterations: Count,
+    num_samples: Count,
+    seed: u64,
+    /// The operation and memory ordering of the ping-pong benches, null for the default of each bench
+    op: Option<Op>,
+    ordering: Option<MemoryOrdering>,
+    cores: Vec<usize>,
+    /// Where each core sits in the machine, when available
+    topology: Option<Vec<CpuTopology>>,
+    benches: Vec<JsonBench<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonBench<'a> {
+    name: &'a str,
+    /// The number of iterations per sample and of samples per pair, once scaled down to the time budget
+    num_iterations: Count,
+    num_samples: Count,
+    /// The cores of the rows and columns of the matrices
+    cores: Vec<usize>,
+    /// Whether [i][j] and [j][i] are the same measurement. Otherwise, [i][j] is core i receiving from core j.
+    symmetric: bool,
+    stats: JsonStats,
+    /// Indexed by [i][j][sample]. The warmup samples discarded before measuring each pair.
+    warmup: Vec<Vec<Vec<Option<f64>>>>,
+    /// Indexed by [i][j][sample]. null when the pair was not measured.
+    /// Outliers are included.
+    samples: Vec<Vec<Vec<Option<f64>>>>,
+}
+
+#[derive(Serialize)]
+struct JsonStats {
+    /// The statistic used to find the min and max pairs
+    stat: &'static str,
+    /// Each statistic for each pair, along with the standard deviation of the mean
+    pairs: BTreeMap<&'static str, Vec<Vec<Option<f64>>>>,
+    min_pair: JsonPair,
+    max_pair: JsonPair,
+    /// The number of samples measured for each pair
+    num_samples: Vec<Vec<usize>>,
+    /// The number of samples rejected as outliers for each pair
+    dropped: Vec<Vec<usize>>,
+    /// Each statistic over all the samples
+    global: BTreeMap<&'static str, f64>,
+    bootstrap: Option<JsonBootstrap>,
+}
+
+/// Confidence intervals are given as [low, high]
+#[derive(Serialize)]
+struct JsonBootstrap {
+    confidence: f64,
+    num_resamples: usize,
+    pair_mean: Vec<Vec<Option<(f64, f64)>>>,
+    global_mean: (f64, f64),
+    min_mean: (f64, f64),
+    max_mean: (f64, f64),
+}
+
+impl JsonBootstrap {
+    fn new(b: &Bootstrap) -> Self {
+        let pair_mean = b.pair_mean.rows().into_iter()
+            .map(|row| row.iter().map(|&(low, high)| to_option(low).map(|low| (low, high))).collect())
+            .collect();
+        Self {
+            confidence: stats::CONFIDENCE,
+            num_resamples: b.num_resamples,
+            pair_mean,
+            global_mean: b.global_mean,
+            min_mean: b.min_mean,
+            max_mean: b.max_mean,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonPair {
+    cores: (usize, usize),
+    value: f64,
+    stddev: f64,
+}
+
+fn to_option(v: f64) -> Option<f64> {
+    if v.is_nan() { None } else { Some(v) }
+}
+
+fn lane_to_vec(lane: ArrayView1<f64>) -> Vec<Option<f64>> {
+    lane.iter().copied().map(to_option).collect()
+}
+
+fn matrix_to_vec(matrix: &Array2<f64>) -> Vec<Vec<Option<f64>>> {
+    matrix.rows().into_iter().map(lane_to_vec).collect()
+}
+
+fn tensor_to_vec(tensor: &Results) -> Vec<Vec<Vec<Option<f64>>>> {
+    tensor.outer_iter()
+        .map(|row| row.outer_iter().map(lane_to_vec).collect())
+        .collect()
+}
+
+impl JsonPair {
+    fn new(cores: &[CoreId], summary: &Summary, (i, j): (usize, usize)) -> Self {
+        Self {
+            cores: (cores[i].id, cores[j].id),
+            value: summary.value[(i, j)],
+            stddev: summary.stddev[(i, j)],
+        }
+    }
+}
+
+impl<'a> JsonBench<'a> {
+    fn new(stat: Stat, bench: &'a LatencyMatrix) -> Self {
+        let cores = &bench.cores;
+        let summary = Summary::new(&bench.results, stat);
+        let samples = tensor_to_vec(&bench.raw);
+
+        let mut pairs = Stat::ALL.iter()
+            .map(|&stat| (stat.name(), matrix_to_vec(&stats::pair_matrix(&bench.results, |v| stat.of_sorted(v)))))
+            .collect::<BTreeMap<_, _>>();
+        pairs.insert("stddev", matrix_to_vec(&summary.stddev));
+
+        let global = Stat::ALL.iter()
+            .map(|&stat| (stat.name(), summary.global(stat)))
+            .collect();
+
+        Self {
+            name: &bench.name,
+            num_iterations: bench.config.num_iterations,
+            num_samples: bench.config.num_samples,
+            cores: cores.iter().map(|c| c.id).collect(),
+            symmetric: bench.symmetric,
+            stats: JsonStats {
+                stat: stat.name(),
+                pairs,
+                min_pair: JsonPair::new(cores, &summary, summary.min),
+                max_pair: JsonPair::new(cores, &summary, summary.max),
+                num_samples: bench.num_samples().rows().into_iter().map(|r| r.to_vec()).collect(),
+                dropped: bench.dropped().rows().into_iter().map(|r| r.to_vec()).collect(),
+                global,
+                bootstrap: bench.bootstrap.as_ref().map(JsonBootstrap::new),
+            },
+            warmup: tensor_to_vec(&bench.warmup),
+            samples,
+        }
+    }
+}
+
+fn format_cell(v: f64) -> String {
+    if v.is_nan() { "".to_string() } else { v.to_string() }
+}
+
+pub fn write_csv(
+    out: &mut impl Write,
+    format: CsvFormat,
+    stat: Stat,
+    bench: &LatencyMatrix,
+) -> io::Result<()> {
+    let cores = &bench.cores;
+    match format {
+        CsvFormat::Bare | CsvFormat::Labelled => {
+            let labelled = format == CsvFormat::Labelled;
+            let matrix = stats::pair_matrix(&bench.results, |v| stat.of_sorted(v));
+
+            if labelled {
+                let header = cores.iter().map(|c| c.id.to_string()).collect::<Vec<_>>().join(",");
+                writeln!(out, ",{}", header)?;
+            }
+
+            for (core, row) in cores.iter().zip(matrix.rows()) {
+                let row = row.iter()
+                    .copied()
+                    .map(format_cell)
+                    .collect::<Vec<_>>().join(",");
+                if labelled {
+                    writeln!(out, "{},{}", core.id, row)?;
+                } else {
+                    writeln!(out, "{}", row)?;
+                }
+            }
+        }
+        CsvFormat::Long => {
+            writeln!(out, "bench,core_i,core_j,sample,latency_ns")?;
+            // This is a full export, so outliers are included
+            for ((i, j, s), v) in bench.raw.indexed_iter() {
+                if !v.is_nan() {
+                    writeln!(out, "{},{},{},{},{}", bench.name, cores[i].id, cores[j].id, s, v)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the CSV output of a bench to the given file, creating its parent directories
+pub fn write_csv_file(
+    path: &Path,
+    format: CsvFormat,
+    stat: Stat,
+    bench: &LatencyMatrix,
+) -> io::Result<()> {
+    write_file(path, |out| write_csv(out, format, stat, bench))
+}
+
+/// Writes a file with the given function, creating its parent directories
+pub fn write_file(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    write(&mut writer)?;
+    writer.flush()
+}
+
+/// Writes the contention curve, one row per number of threads, or one row per sample in the long format.
+/// Each row is labelled with the number of threads and the core of the last thread added.
+pub fn write_contention_csv(
+    out: &mut impl Write,
+    format: CsvFormat,
+    stat: Stat,
+    points: &[ContentionPoint],
+) -> io::Result<()> {
+    let core = |point: &ContentionPoint| point.cores.last().map(|c| c.id).unwrap_or_default();
+    match format {
+        CsvFormat::Bare | CsvFormat::Labelled => {
+            if format == CsvFormat::Labelled {
+                writeln!(out, "threads,core,latency_ns,throughput_ops_per_us")?;
+            }
+            for point in points {
+                let latency = stat.of_sorted(&stats::sorted_values(&point.latency));
+                let throughput = stat.of_sorted(&stats::sorted_values(&point.throughput));
+                writeln!(out, "{},{},{},{}", point.cores.len(), core(point), latency, throughput)?;
+            }
+        }
+        CsvFormat::Long => {
+            writeln!(out, "threads,core,sample,latency_ns,throughput_ops_per_us")?;
+            for point in points {
+                for (s, (latency, throughput)) in point.latency.iter().zip(&point.throughput).enumerate() {
+                    writeln!(out, "{},{},{},{},{}", point.cores.len(), core(point), s, latency, throughput)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes every sample of every bench, along with the run parameters and summary statistics
+pub fn write_json(
+    path: &Path,
+    cores: &[CoreId],
+    topology: Option<&Topology>,
+    config: &Config,
+    stat: Stat,
+    benches: &[LatencyMatrix],
+) -> std::io::Result<()> {
+    let report = JsonReport {
+        cpu: utils::get_cpu_brand(),
+        num_iterations: config.num_iterations,
+        num_samples: config.num_samples,
+        seed: config.seed,
+        op: config.op,
+        ordering: config.ordering,
+        cores: cores.iter().map(|c| c.id).collect(),
+        topology: topology.map(|t| cores.iter()
+            .map(|c| t.get(c.id).cloned().unwrap_or(CpuTopology { id: c.id, ..Default::default() }))
+            .collect()),
+        benches: benches.iter().map(|b| JsonBench::new(stat, b)).collect(),
+    };
+
+    write_file(path, |writer| serde_json::to_writer(writer, &report).map_err(io::Error::from))
+}
@@ -0,0 +1,142 @@
+use core_affinity::CoreId;
+use ndarray::Array2;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::bench::Config;
+use crate::error::{Error, Result};
+use crate::topology::{Boundary, Relationship, Topology};
+
+/// Which pairs of cores to measure
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PairSampling {
+    /// All pairs
+    All,
+    /// A random subset of <NUM_PAIRS> pairs
+    Random,
+    /// <PAIRS_PER_CLASS> random pairs for each topology relationship, e.g., shared L3 or cross socket
+    Class,
+}
+
+/// Returns the pairs to measure, as a matrix indexed like the results
+pub fn select_pairs(
+    cores: &[CoreId],
+    topology: Option<&Topology>,
+    config: &Config,
+    symmetric: bool,
+    rng: &mut impl Rng,
+) -> Result<Array2<bool>> {
+    let n = cores.len();
+    let candidates = (0..n)
+        .flat_map(|i| (0..n).map(move |j| (i, j)))
+        .filter(|&(i, j)| if symmetric { i > j } else { i != j })
+        .filter(|&(i, j)| config.smt.selects_pair(cores[i], cores[j], topology))
+        .collect::<Vec<_>>();
+
+    let selected = match (config.pairs, topology) {
+        (PairSampling::All, _) => candidates,
+        (PairSampling::Random, _) => candidates.choose_multiple(rng, config.num_pairs).copied().collect(),
+        (PairSampling::Class, Some(topology)) => {
+            let mut classes = BTreeMap::<Relationship, Vec<_>>::new();
+            for (i, j) in candidates {
+                classes.entry(topology.relationship(cores[i].id, cores[j].id)).or_default().push((i, j));
+            }
+            classes.values()
+                .flat_map(|pairs| pairs.choose_multiple(rng, config.pairs_per_class).copied().collect::<Vec<_>>())
+                .collect()
+        }
+        (PairSampling::Class, None) => return Err(Error::InvalidArgs("The cpu topology is needed for --pairs class, but is not available".to_string())),
+    };
+
+    let mut matrix = Array2::from_elem((n, n), false);
+    for p in selected {
+        matrix[p] = true;
+    }
+    Ok(matrix)
+}
+
+/// Groups the measured pairs by topology relationship
+pub fn classify_pairs(cores: &[CoreId], topology: &Topology, measured: &Array2<f64>) -> BTreeMap<Relationship, Vec<(usize, usize)>> {
+    let mut classes = BTreeMap::<Relationship, Vec<_>>::new();
+    for ((i, j), v) in measured.indexed_iter() {
+        if !v.is_nan() {
+            classes.entry(topology.relationship(cores[i].id, cores[j].id)).or_default().push((i, j));
+        }
+    }
+    classes
+}
+
+/// The socket and L3 domain of a core, when the topology knows the core
+type Domain = Option<(Option<usize>, Option<usize>)>;
+
+/// Splits the pairs into batches of up to `max_concurrent` pairs that don't share cores.
+/// Within a batch, we prefer pairs whose cores are in other L3 domains and sockets than
+/// the cores already in the batch, to limit the interference between concurrent pairs.
+pub fn schedule_batches(
+    pairs: Vec<(usize, usize)>,
+    cores: &[CoreId],
+    topology: Option<&Topology>,
+    max_concurrent: usize,
+) -> Vec<Vec<(usize, usize)>> {
+    if max_concurrent <= 1 {
+        return pairs.into_iter().map(|p| vec![p]).collect();
+    }
+
+    let domains = cores.iter()
+        .map(|c| topology.and_then(|t| t.get(c.id)).map(|t| (t.package, t.l3)))
+        .collect::<Vec<Domain>>();
+
+    // The pairs are queued by the domains of their cores, in their original order.
+    // All the pairs of a queue are as far from the cores already in the batch.
+    let mut queues = BTreeMap::<(Domain, Domain), VecDeque<(usize, (usize, usize))>>::new();
+    for (k, (i, j)) in pairs.into_iter().enumerate() {
+        queues.entry((domains[i], domains[j])).or_default().push_back((k, (i, j)));
+    }
+
+    let mut batches = Vec::new();
+    while !queues.is_empty() {
+        let mut batch: Vec<(usize, usize)> = Vec::new();
+        let mut used: Vec<usize> = Vec::new();
+
+        // Closest boundary between a domain and the cores already in the batch
+        let closest = |used: &[usize], domain: Domain| {
+            used.iter()
+                .map(|&u| match (domain, domains[u]) {
+                    (Some(d), Some(du)) if d == du => Boundary::None,
+                    (Some((socket, _)), Some((socket_u, _))) if socket == socket_u => Boundary::L3,
+                    _ => Boundary::Socket,
+                })
+                .min()
+                .unwrap_or(Boundary::Socket)
+        };
+
+        while batch.len() < max_concurrent {
+            // The first pair of each queue that doesn't use the cores of the batch. On a tie, the earliest pair.
+            let best = queues.iter()
+                .filter_map(|(&(a, b), queue)| {
+                    let k = queue.iter().position(|&(_, (i, j))| !used.contains(&i) && !used.contains(&j))?;
+                    Some((closest(&used, a).min(closest(&used, b)), Reverse(queue[k].0), (a, b), k))
+                })
+                .max();
+            match best {
+                Some((_, _, key, k)) => {
+                    let queue = queues.get_mut(&key).unwrap();
+                    let (_, (i, j)) = queue.remove(k).unwrap();
+                    if queue.is_empty() {
+                        queues.remove(&key);
+                    }
+                    used.extend([i, j]);
+                    batch.push((i, j));
+                }
+                None => break,
+            }
+        }
+
+        batches.push(batch);
+    }
+
+    batches
+}
@@ -0,0 +1,439 @@
+use ansi_term::Color;
+use core_affinity::CoreId;
+use core_to_core_latency::bench::{self, BenchKind, Config, Event, LatencyMatrix, Results, Sweep};
+use core_to_core_latency::analysis;
+use core_to_core_latency::budget;
+use core_to_core_latency::checkpoint::Checkpoint;
+use core_to_core_latency::contention::{self, ContentionOp, ContentionPoint};
+use core_to_core_latency::error::Result;
+use core_to_core_latency::cpulist::CoreOrder;
+use core_to_core_latency::pairs::{self, PairSampling};
+use core_to_core_latency::stats::{self, Bootstrap, OutlierFilter, Stat, Summary};
+use core_to_core_latency::topology::{Boundary, Topology};
+use ndarray::{s, Array2, Axis};
+use quanta::Clock;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::io::Write;
+
+use crate::CliArgs;
+
+/// The layout of the printed matrices, with separators between L3 domains and sockets
+struct Grid<'a> {
+    cores: &'a [CoreId],
+    /// The boundary between the core i-1 and the core i
+    boundaries: Vec<Boundary>,
+}
+
+impl<'a> Grid<'a> {
+    fn new(cores: &'a [CoreId], topology: Option<&Topology>) -> Self {
+        let boundaries = (0..cores.len()).map(|i| match (i, topology) {
+            (0, _) | (_, None) => Boundary::None,
+            (i, Some(topology)) => topology.boundary(cores[i-1].id, cores[i].id),
+        }).collect();
+        Self { cores, boundaries }
+    }
+
+    fn print_column_separator(&self, j: usize) {
+        match self.boundaries[j] {
+            Boundary::None => {}
+            Boundary::L3 => eprint!("{}", Color::White.dimmed().paint(" │")),
+            Boundary::Socket => eprint!("{}", Color::White.dimmed().paint(" ║")),
+        }
+    }
+
+    fn print_header(&self) {
+        eprint!("    {: >3}", "");
+        for (j, core_j) in self.cores.iter().enumerate() {
+            self.print_column_separator(j);
+            eprint!(" {: >4}{: >3}", core_j.id, "");
+            //        |||
+            //        ||+-- Width
+            //        |+--- Align
+            //        +---- Fill
+        }
+        eprintln!();
+    }
+
+    /// Prints the row label, preceded by a separator line when the row starts a new group
+    fn start_row(&self, i: usize) {
+        let (line, cross) = match self.boundaries[i] {
+            Boundary::None => ("", ""),
+            Boundary::L3 => ("─", "─┼"),
+            Boundary::Socket => ("═", "═╬"),
+        };
+        if !line.is_empty() {
+            let mut separator = line.repeat(7);
+            for j in 0..self.cores.len() {
+                if self.boundaries[j] != Boundary::None {
+                    separator += cross;
+                }
+                separator += &line.repeat(8);
+            }
+            eprintln!("{}", Color::White.dimmed().paint(separator));
+        }
+        eprint!("    {: >3}", self.cores[i].id);
+    }
+
+    /// Prints a matrix laid out like the one printed while benchmarking, skipping NaN cells
+    fn print_matrix(&self, matrix: &Array2<f64>, format: impl Fn((usize, usize), f64) -> String) {
+        self.print_header();
+        for (i, row) in matrix.rows().into_iter().enumerate() {
+            self.start_row(i);
+            for (j, &v) in row.iter().enumerate() {
+                self.print_column_separator(j);
+                if v.is_nan() {
+                    eprint!("{: >8}", "");
+                } else {
+                    eprint!(" {}", format((i, j), v));
+                }
+            }
+            eprintln!();
+        }
+    }
+
+    /// Prints a matrix of counts, only on the pairs that were measured
+    fn print_count_matrix(&self, counts: &Array2<usize>, measured: &Array2<f64>) {
+        let counts = Array2::from_shape_fn(counts.dim(), |(i, j)| {
+            if measured[(i, j)].is_nan() { f64::NAN } else { counts[(i, j)] as f64 }
+        });
+        self.print_matrix(&counts, |_, v| format!("{: >4}{: >3}", v, ""));
+    }
+}
+
+/// Prints the matrix cell by cell as the pairs are measured, row by row
+struct LiveGrid<'a> {
+    grid: &'a Grid<'a>,
+    /// Symmetric benches only measure the lower triangle
+    symmetric: bool,
+    started: bool,
+    /// The next cell to print
+    next: (usize, usize),
+}
+
+impl<'a> LiveGrid<'a> {
+    fn new(grid: &'a Grid<'a>, symmetric: bool) -> Self {
+        Self { grid, symmetric, started: false, next: (0, 0) }
+    }
+
+    fn row_len(&self, i: usize) -> usize {
+        if self.symmetric { i } else { self.grid.cores.len() }
+    }
+
+    /// Prints the next cell, or a blank one, and ends the row with the ETA after its last cell
+    fn step(&mut self, cell: Option<String>, eta: &str) {
+        if !self.started {
+            self.grid.print_header();
+            self.started = true;
+        }
+
+        let (i, j) = self.next;
+        if j == 0 {
+            self.grid.start_row(i);
+        }
+        if j < self.row_len(i) {
+            self.grid.print_column_separator(j);
+            match cell {
+                Some(cell) => eprint!(" {}", cell),
+                None => eprint!("{: >8}", ""),
+            }
+            self.next = (i, j + 1);
+        }
+        if self.next.1 == self.row_len(i) {
+            match eta {
+                "" => eprintln!(),
+                eta => eprintln!("  {}", Color::White.dimmed().paint(eta)),
+            }
+            self.next = (i + 1, 0);
+        }
+    }
+
+    /// Prints the cell of a pair, after blanks for the pairs that are not measured
+    fn print_cell(&mut self, pair: (usize, usize), cell: String, eta: &str) {
+        while self.next != pair {
+            self.step(None, eta);
+        }
+        self.step(Some(cell), eta);
+    }
+
+    /// Prints the remaining rows
+    fn finish(&mut self) {
+        while !self.started || self.next.0 < self.grid.cores.len() {
+            self.step(None, "");
+        }
+    }
+}
+
+/// Prints how far each warmup sample is from the steady state median, as the median over all pairs
+fn print_warmup(results: &LatencyMatrix) {
+    let num_warmup = results.warmup.len_of(Axis(2));
+    if num_warmup == 0 {
+        return;
+    }
+
+    let steady = stats::pair_matrix(&results.results, |v| Stat::Median.of_sorted(v));
+    let deviations = (0..num_warmup).map(|k| {
+        let deviations = results.warmup.slice(s![.., .., k]).iter().zip(steady.iter())
+            .map(|(w, m)| 100.0 * (w / m - 1.0))
+            .collect::<Vec<_>>();
+        Stat::Median.of_sorted(&stats::sorted_values(&deviations))
+    }).collect::<Vec<_>>();
+
+    let formatted = deviations.iter().enumerate()
+        .map(|(k, d)| format!("#{} {:+.1}%", k+1, d))
+        .collect::<Vec<_>>().join(", ");
+    eprintln!("    Warmup:       {} from steady state", formatted);
+
+    // Beyond a few percents, the last warmup sample was probably not at steady state either
+    let last = deviations[num_warmup-1];
+    if last.abs() > 5.0 {
+        eprintln!("    {}", Color::Yellow.paint(format!(
+            "WARN the last warmup sample is {:+.1}% from steady state, consider more --warmup-samples or --warmup-ms", last)));
+    }
+}
+
+/// Measures the matrix while printing it, then prints the summary statistics
+pub fn run_bench(
+    cores: &[CoreId],
+    topology: Option<&Topology>,
+    clock: &Clock,
+    args: &CliArgs,
+    config: &Config,
+    checkpoint: Option<&mut Checkpoint>,
+) -> Result<LatencyMatrix> {
+    let grid = Grid::new(cores, topology);
+
+    let mcolor = Color::White.bold();
+    let scolor = Color::White.dimmed();
+
+    let format_cell = |value: f64, stddev: f64| {
+        let value = format!("{: >4.0}", value);
+        let stddev = if args.stat == Stat::Mean {
+            // We apply the central limit theorem to estimate the standard deviation
+            format!("±{: <2.0}", stddev.min(99.0))
+        } else {
+            format!("{: <3}", "")
+        };
+        format!("{}{}", mcolor.paint(value), scolor.paint(stddev))
+    };
+
+    // Without passes or concurrent pairs, the cells are printed as soon as they are measured
+    let live = config.passes == 1 && config.parallel == 1;
+    let mut live_grid = LiveGrid::new(&grid, config.bench.symmetric);
+
+    let results = bench::measure_matrix(cores, topology, clock, config, checkpoint, |event| match event {
+        Event::Scaled(fit) => {
+            let scaled = match fit.changes.is_empty() {
+                true => "no scaling needed".to_string(),
+                false => format!("scaled {}", fit.changes.join(", ")),
+            };
+            eprintln!("    Time budget {}: {}, estimated {}",
+                budget::format_duration(config.time_budget.unwrap_or_default()), scaled, budget::format_duration(fit.estimate));
+            eprintln!();
+        }
+        Event::Measured { pair, samples, eta, .. } if live => {
+            let values = stats::sorted_values(samples);
+            live_grid.print_cell(pair, format_cell(args.stat.of_sorted(&values), stats::stderr(&values)), &eta.format());
+            let _ = std::io::stdout().lock().flush();
+        }
+        Event::Measured { pass, num_measured, num_pairs, eta, .. } => {
+            let mut progress = format!("Measured {}/{} pairs", num_measured, num_pairs);
+            if config.passes > 1 {
+                progress = format!("Pass {}/{}: {}", pass+1, config.passes, progress);
+            }
+            if config.parallel > 1 {
+                progress = format!("{}, up to {} at a time", progress, config.parallel);
+            }
+            // Pad to clear the end of a longer previous line
+            eprint!("\r    {} {: <12}", progress, eta.format());
+        }
+    })?;
+
+    if live {
+        live_grid.finish();
+    } else {
+        eprintln!();
+        eprintln!();
+
+        if let Some(interference) = &results.interference {
+            let concurrent = args.stat.of_sorted(&stats::sorted_values(&interference.concurrent));
+            let alone = args.stat.of_sorted(&stats::sorted_values(&interference.alone));
+            let (core_i, core_j) = interference.cores;
+            eprintln!("    Interference: cores ({},{}) measured {:.1}ns concurrently and {:.1}ns alone ({:+.1}%)",
+                core_i.id, core_j.id, concurrent, alone, 100.0 * (concurrent / alone - 1.0));
+            eprintln!();
+        }
+
+        let value = stats::pair_matrix(&results.results, |v| args.stat.of_sorted(v));
+        let stddev = stats::pair_matrix(&results.results, stats::stderr);
+        grid.print_matrix(&value, |p, v| format_cell(v, stddev[p]));
+    }
+
+    eprintln!();
+
+    let mut results = results;
+    let config = results.config.clone();
+
+    if args.order == CoreOrder::Latency {
+        let order = analysis::cluster_order(&Summary::new(&results.results, args.stat).value);
+        results.reorder(&order);
+
+        let summary = Summary::new(&results.results, args.stat);
+        eprintln!("    Reordered by latency:");
+        eprintln!();
+        Grid::new(&results.cores, topology)
+            .print_matrix(&summary.value, |(i, j), v| format_cell(v, summary.stddev[(i, j)]));
+        eprintln!();
+    }
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    results.bootstrap = args.bootstrap.map(|n| Bootstrap::new(&results.results, n, &mut rng));
+    let summary = Summary::new(&results.results, args.stat);
+    let cores = &results.cores;
+    let grid = Grid::new(cores, topology);
+
+    let format_ci = |(low, high): (f64, f64)| format!("[{:.1}, {:.1}]", low, high);
+
+    // Only the mean gets an error bar, otherwise we say which statistic is shown
+    let format_pair = |summary: &Summary, (i, j): (usize, usize), ci: Option<(f64, f64)>| {
+        let value = format!("{:.1}", summary.value[(i, j)]);
+        let stddev = match (summary.stat, ci) {
+            (Stat::Mean, Some(ci)) => format_ci(ci),
+            (Stat::Mean, None) => format!("±{:.1}", summary.stddev[(i, j)]),
+            (stat, _) => format!("({})", stat.name()),
+        };
+        format!("{}ns {} cores: ({},{})", mcolor.paint(value), scolor.paint(stddev), cores[i].id, cores[j].id)
+    };
+
+    let print_summary = |indent: &str, summary: &Summary, bootstrap: Option<&Bootstrap>| {
+        // Print min/max latency
+        eprintln!("{}Min  latency: {}", indent, format_pair(summary, summary.min, bootstrap.map(|b| b.min_mean)));
+        eprintln!("{}Max  latency: {}", indent, format_pair(summary, summary.max, bootstrap.map(|b| b.max_mean)));
+
+        // Print mean latency
+        let mean = format!("{:.1}", summary.global(Stat::Mean));
+        match bootstrap {
+            Some(b) => eprintln!("{}Mean latency: {}ns {}", indent, mcolor.paint(mean), scolor.paint(format_ci(b.global_mean))),
+            // no stddev, it's hard to put a value that is meaningful without a lengthy explanation
+            None => eprintln!("{}Mean latency: {}ns", indent, mcolor.paint(mean)),
+        }
+    };
+
+    print_summary("    ", &summary, results.bootstrap.as_ref());
+
+    if let Some(b) = &results.bootstrap {
+        eprintln!("    Intervals are {:.0}% bootstrap confidence intervals of the mean, over {} resamples",
+            100.0 * stats::CONFIDENCE, b.num_resamples);
+    }
+
+    // Print the percentiles over all samples
+    {
+        let percentiles = [Stat::Min, Stat::Median, Stat::P90, Stat::P99].iter()
+            .map(|&stat| format!("{} {}ns", stat.name(), mcolor.paint(format!("{:.1}", summary.global(stat)))))
+            .collect::<Vec<_>>().join(", ");
+        eprintln!("    Percentiles:  {}", percentiles);
+    }
+
+    // Print the summary of each topology relationship when only some pairs are measured
+    if let (Some(topology), true) = (topology, config.pairs != PairSampling::All) {
+        for (class, class_pairs) in pairs::classify_pairs(cores, topology, &summary.value) {
+            let mut class_results = Results::from_elem(results.results.dim(), f64::NAN);
+            for &(i, j) in &class_pairs {
+                class_results.slice_mut(s![i, j, ..]).assign(&results.results.slice(s![i, j, ..]));
+            }
+            eprintln!("    {} ({} pairs):", class.name(), class_pairs.len());
+            print_summary("        ", &Summary::new(&class_results, args.stat), None);
+        }
+    }
+
+    // Print the latency tiers and groups of cores inferred from the matrix
+    {
+        let tiers = analysis::infer_tiers(&summary.value);
+        eprintln!("    Tiers:        {}", analysis::describe_tiers(&tiers));
+        if let Some(disagreement) = topology.and_then(|t| analysis::compare_with_l3(&tiers, cores, t)) {
+            eprintln!("    {}", Color::Yellow.paint(format!("Topology:     {}", disagreement)));
+        }
+    }
+
+    print_warmup(&results);
+
+    // Print the number of outliers rejected in each pair
+    if config.outliers != OutlierFilter::None {
+        let dropped = results.dropped();
+        let total_dropped = dropped.sum();
+        let total = results.raw.iter().filter(|v| !v.is_nan()).count();
+        eprintln!("    Outliers:     {} of {} samples dropped ({:.2}%), per pair:",
+            total_dropped, total, 100.0 * total_dropped as f64 / total as f64);
+        eprintln!();
+        grid.print_count_matrix(&dropped, &summary.value);
+    }
+
+    // Print the number of samples used in each pair
+    if let Some(target) = config.target_stderr {
+        let num_samples = results.num_samples();
+        let max_samples = config.max_samples() as usize;
+        let capped = num_samples.iter().zip(summary.stddev.iter())
+            .filter(|(&n, &stddev)| n >= max_samples && stddev > target)
+            .count();
+        eprintln!("    Samples:      {} in total, {} pairs capped at {} samples without reaching ±{}ns, per pair:",
+            num_samples.sum(), capped, max_samples, target);
+        eprintln!();
+        grid.print_count_matrix(&num_samples, &summary.value);
+    }
+
+    Ok(results)
+}
+
+/// Measures the contention curve while printing it, one row per number of threads
+pub fn run_contention(cores: &[CoreId], clock: &Clock, args: &CliArgs, config: &Config, op: ContentionOp) -> Result<Vec<ContentionPoint>> {
+    let mcolor = Color::White.bold();
+    let scolor = Color::White.dimmed();
+
+    let format_stat = |samples: &[f64], unit: &str| {
+        let values = stats::sorted_values(samples);
+        let value = format!("{:.1}{}", args.stat.of_sorted(&values), unit);
+        let stddev = match args.stat {
+            Stat::Mean => format!("±{:.1}", stats::stderr(&values)),
+            stat => format!("({})", stat.name()),
+        };
+        format!("{} {}", mcolor.paint(format!("{: >12}", value)), scolor.paint(format!("{: <8}", stddev)))
+    };
+
+    eprintln!("    {: >7}  {: >5}  {: >12} {: <8} {: >12}", "Threads", "Core", "Latency/op", "", "Throughput");
+    let points = contention::measure_contention(cores, op, clock, config, |point| {
+        let core = point.cores.last().map(|c| c.id).unwrap_or_default();
+        eprintln!("    {: >7}  {: >5}  {} {}",
+            point.cores.len(), core, format_stat(&point.latency, "ns"), format_stat(&point.throughput, " ops/µs"));
+    })?;
+    eprintln!();
+
+    Ok(points)
+}
+
+/// Prints the results of each value of the sweep, over all the pairs, and how they compare to the baseline
+pub fn print_sweep_curve(kind: &BenchKind, sweep: Sweep, runs: &[&LatencyMatrix], stat: Stat) {
+    let mcolor = Color::White.bold();
+    let baseline = sweep.baseline(runs.iter().map(|run| sweep.value(&run.config)))
+        .and_then(|value| runs.iter().find(|run| sweep.value(&run.config) == value));
+
+    eprintln!();
+    eprintln!("{}: {}", kind.name, kind.description);
+    match baseline {
+        Some(run) => eprintln!("By {}, {} over all pairs, compared to {}", sweep.name(), stat.name(), run.name),
+        None => eprintln!("By {}, {} over all pairs", sweep.name(), stat.name()),
+    }
+    let baseline = baseline.map(|run| Summary::new(&run.results, stat).global(stat));
+    eprintln!();
+    eprintln!("    {: >5}  {: >10}  {: >8}  {: >20}  {: >20}", sweep.label(), "Time", "Change", "Min pair", "Max pair");
+    for run in runs {
+        let summary = Summary::new(&run.results, stat);
+        let cores = &run.cores;
+        let format_pair = |(i, j): (usize, usize)| format!("{:.1}ns ({},{})", summary.value[(i, j)], cores[i].id, cores[j].id);
+        let global = summary.global(stat);
+        let change = baseline.map(|b| format!("{:+.1}%", (global / b - 1.0) * 100.0)).unwrap_or_default();
+        eprintln!("    {: >5}  {}  {: >8}  {: >20}  {: >20}",
+            sweep.value(&run.config), mcolor.paint(format!("{: >8.1}ns", global)), change,
+            format_pair(summary.min), format_pair(summary.max));
+    }
+    eprintln!();
+}
@@ -0,0 +1,228 @@
+use ndarray::{s, Array2, Axis};
+use ordered_float::NotNan;
+use rand::Rng;
+
+use crate::bench::Results;
+
+/// A statistic computed over the samples of a pair
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum Stat {
+    Mean,
+    Median,
+    Min,
+    P90,
+    P99,
+}
+
+impl Stat {
+    pub const ALL: [Stat; 5] = [Stat::Mean, Stat::Median, Stat::Min, Stat::P90, Stat::P99];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stat::Mean => "mean",
+            Stat::Median => "median",
+            Stat::Min => "min",
+            Stat::P90 => "p90",
+            Stat::P99 => "p99",
+        }
+    }
+
+    /// Computes the statistic on sorted values, which must not contain NaNs.
+    /// Returns NaN when there are no values.
+    pub fn of_sorted(self, sorted: &[f64]) -> f64 {
+        if sorted.is_empty() {
+            return f64::NAN;
+        }
+        match self {
+            Stat::Mean => sorted.iter().sum::<f64>() / sorted.len() as f64,
+            Stat::Median => percentile(sorted, 50.0),
+            Stat::Min => sorted[0],
+            Stat::P90 => percentile(sorted, 90.0),
+            Stat::P99 => percentile(sorted, 99.0),
+        }
+    }
+}
+
+/// How to reject the samples disturbed by interrupts or preemption
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutlierFilter {
+    /// Keep all samples
+    None,
+    /// Drop samples further than 3 scaled median absolute deviations from the median
+    Mad,
+    /// Drop samples further than 1.5 interquartile ranges outside of the quartiles
+    Iqr,
+}
+
+impl OutlierFilter {
+    /// Returns the samples in the same order, with outliers replaced by NaN
+    pub fn reject(self, samples: &[f64]) -> Vec<f64> {
+        let sorted = sorted_values(samples);
+        if sorted.is_empty() {
+            return samples.to_vec();
+        }
+
+        let (low, high) = match self {
+            OutlierFilter::None => return samples.to_vec(),
+            OutlierFilter::Mad => {
+                let median = percentile(&sorted, 50.0);
+                let deviations = sorted_values(&sorted.iter().map(|v| (v - median).abs()).collect::<Vec<_>>());
+                // 1.4826 makes the MAD a consistent estimator of the standard deviation
+                let mad = 1.4826 * percentile(&deviations, 50.0);
+                (median - 3.0 * mad, median + 3.0 * mad)
+            }
+            OutlierFilter::Iqr => {
+                let (q1, q3) = (percentile(&sorted, 25.0), percentile(&sorted, 75.0));
+                let iqr = q3 - q1;
+                (q1 - 1.5 * iqr, q3 + 1.5 * iqr)
+            }
+        };
+
+        // A zero spread happens with quantized clocks. Rejecting everything but one value is not useful.
+        if low == high {
+            return samples.to_vec();
+        }
+
+        samples.iter()
+            .map(|&v| if (low..=high).contains(&v) { v } else { f64::NAN })
+            .collect()
+    }
+}
+
+/// Returns the non-NaN values, sorted
+pub fn sorted_values<'a>(values: impl IntoIterator<Item = &'a f64>) -> Vec<f64> {
+    let mut values = values.into_iter().copied().filter(|v| !v.is_nan()).collect::<Vec<_>>();
+    values.sort_by(f64::total_cmp);
+    values
+}
+
+/// Percentile with linear interpolation between the closest ranks, `p` is in [0, 100]
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+}
+
+/// Standard deviation of the mean. We apply the central limit theorem.
+pub fn stderr(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    if values.len() < 2 {
+        return f64::NAN;
+    }
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (variance / n).sqrt()
+}
+
+/// Applies `f` on the sorted samples of each pair
+pub fn pair_matrix(results: &Results, f: impl Fn(&[f64]) -> f64) -> Array2<f64> {
+    results.map_axis(Axis(2), |lane| f(&sorted_values(lane)))
+}
+
+/// Summary statistics of a results tensor
+pub struct Summary {
+    /// The statistic used to compare pairs
+    pub stat: Stat,
+    /// The statistic of each pair
+    pub value: Array2<f64>,
+    /// Standard deviation of the mean of each pair
+    pub stddev: Array2<f64>,
+    /// Indices of the pair with the lowest value
+    pub min: (usize, usize),
+    /// Indices of the pair with the highest value
+    pub max: (usize, usize),
+    /// All the samples of all pairs, sorted
+    all_samples: Vec<f64>,
+}
+
+impl Summary {
+    pub fn new(results: &Results, stat: Stat) -> Self {
+        let value = pair_matrix(results, |v| stat.of_sorted(v));
+        let stddev = pair_matrix(results, stderr);
+
+        let (min, _) = value.indexed_iter()
+            .filter_map(|(i, v)| NotNan::new(*v).ok().map(|v| (i, v)))
+            .min_by_key(|(_, v)| *v)
+            .unwrap();
+        let (max, _) = value.indexed_iter()
+            .filter_map(|(i, v)| NotNan::new(*v).ok().map(|v| (i, v)))
+            .max_by_key(|(_, v)| *v)
+            .unwrap();
+
+        let all_samples = sorted_values(results.iter());
+
+        Self { stat, value, stddev, min, max, all_samples }
+    }
+
+    /// The statistic computed over all the samples of all pairs
+    pub fn global(&self, stat: Stat) -> f64 {
+        stat.of_sorted(&self.all_samples)
+    }
+}
+
+/// Bootstrap confidence intervals, as (low, high)
+pub struct Bootstrap {
+    pub num_resamples: usize,
+    /// Confidence interval of the mean of each pair
+    pub pair_mean: Array2<(f64, f64)>,
+    /// Confidence interval of the mean over all the samples
+    pub global_mean: (f64, f64),
+    /// Confidence interval of the lowest pair mean
+    pub min_mean: (f64, f64),
+    /// Confidence interval of the highest pair mean
+    pub max_mean: (f64, f64),
+}
+
+/// The confidence level of bootstrap intervals
+pub const CONFIDENCE: f64 = 0.95;
+
+fn confidence_interval(mut values: Vec<f64>) -> (f64, f64) {
+    values.sort_by(f64::total_cmp);
+    let tail = 100.0 * (1.0 - CONFIDENCE) / 2.0;
+    (percentile(&values, tail), percentile(&values, 100.0 - tail))
+}
+
+impl Bootstrap {
+    /// Resamples the samples of each pair with replacement `num_resamples` times.
+    /// The global mean, min and max are recomputed on each resampled matrix, so that
+    /// the choice of the min and max pairs is part of their uncertainty.
+    pub fn new(results: &Results, num_resamples: usize, rng: &mut impl Rng) -> Self {
+        let (n, m, _) = results.dim();
+        let mut pair_mean = Array2::from_elem((n, m), (f64::NAN, f64::NAN));
+        let mut global_sum = vec![0.0; num_resamples];
+        let mut global_count = 0;
+        let mut min_mean = vec![f64::INFINITY; num_resamples];
+        let mut max_mean = vec![f64::NEG_INFINITY; num_resamples];
+
+        for ((i, j), pair_mean) in pair_mean.indexed_iter_mut() {
+            let values = sorted_values(results.slice(s![i, j, ..]));
+            if values.is_empty() {
+                continue;
+            }
+
+            let means = (0..num_resamples).map(|b| {
+                let mean = (0..values.len())
+                    .map(|_| values[rng.gen_range(0..values.len())])
+                    .sum::<f64>() / values.len() as f64;
+                global_sum[b] += mean * values.len() as f64;
+                min_mean[b] = min_mean[b].min(mean);
+                max_mean[b] = max_mean[b].max(mean);
+                mean
+            }).collect();
+
+            global_count += values.len();
+            *pair_mean = confidence_interval(means);
+        }
+
+        let global_mean = global_sum.into_iter().map(|sum| sum / global_count as f64).collect();
+
+        Self {
+            num_resamples,
+            pair_mean,
+            global_mean: confidence_interval(global_mean),
+            min_mean: confidence_interval(min_mean),
+            max_mean: confidence_interval(max_mean),
+        }
+    }
+}
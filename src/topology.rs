@@ -0,0 +1,186 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cpulist::parse_cpulist;
+
+pub const DEFAULT_SYSFS_ROOT: &str = "/sys";
+
+/// Where a logical cpu sits in the machine, as reported by the kernel
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CpuTopology {
+    pub id: usize,
+    pub package: Option<usize>,
+    pub die: Option<usize>,
+    pub cluster: Option<usize>,
+    pub core: Option<usize>,
+    pub node: Option<usize>,
+    /// The logical cpus sharing the same physical core, including this one
+    pub smt_siblings: Vec<usize>,
+    /// The L2 cache, identified by the lowest cpu id sharing it
+    pub l2: Option<usize>,
+    /// The L3 cache, identified by the lowest cpu id sharing it
+    pub l3: Option<usize>,
+}
+
+/// The boundary between two cores, from the closest to the furthest apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Boundary {
+    None,
+    L3,
+    Socket,
+}
+
+/// How close two cores are, from the closest to the furthest apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Relationship {
+    SmtSiblings,
+    SharedL2,
+    SharedL3,
+    SameDie,
+    SameSocket,
+    CrossSocket,
+}
+
+impl Relationship {
+    pub fn name(self) -> &'static str {
+        match self {
+            Relationship::SmtSiblings => "SMT siblings",
+            Relationship::SharedL2 => "shared L2",
+            Relationship::SharedL3 => "shared L3",
+            Relationship::SameDie => "same die",
+            Relationship::SameSocket => "same socket",
+            Relationship::CrossSocket => "cross socket",
+        }
+    }
+}
+
+pub struct Topology {
+    cpus: BTreeMap<usize, CpuTopology>,
+}
+
+fn read_id(path: &Path) -> Option<usize> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn read_cpulist(path: &Path) -> Option<Vec<usize>> {
+    parse_cpulist(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// Returns N for the entries of `dir` named `<prefix>N`
+fn numbered_entries(dir: &Path, prefix: &str) -> io::Result<Vec<(usize, PathBuf)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if let Some(n) = name.to_str().and_then(|n| n.strip_prefix(prefix)).and_then(|n| n.parse().ok()) {
+            entries.push((n, entry.path()));
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+impl Topology {
+    /// Reads the topology from `<root>/devices/system`. `root` is normally /sys,
+    /// but can point to a copy of the sysfs tree of another machine.
+    pub fn from_sysfs(root: &Path) -> io::Result<Self> {
+        let system = root.join("devices/system");
+        let mut cpus = BTreeMap::new();
+
+        for (id, path) in numbered_entries(&system.join("cpu"), "cpu")? {
+            let topology = path.join("topology");
+            let mut cpu = CpuTopology {
+                id,
+                package: read_id(&topology.join("physical_package_id")),
+                die: read_id(&topology.join("die_id")),
+                cluster: read_id(&topology.join("cluster_id")),
+                core: read_id(&topology.join("core_id")),
+                smt_siblings: read_cpulist(&topology.join("thread_siblings_list")).unwrap_or_default(),
+                ..Default::default()
+            };
+
+            for (_, index) in numbered_entries(&path.join("cache"), "index").unwrap_or_default() {
+                let cache_id = read_cpulist(&index.join("shared_cpu_list"))
+                    .and_then(|cpus| cpus.into_iter().min());
+                match read_id(&index.join("level")) {
+                    Some(2) => cpu.l2 = cache_id,
+                    Some(3) => cpu.l3 = cache_id,
+                    _ => {}
+                }
+            }
+
+            cpus.insert(id, cpu);
+        }
+
+        // NUMA nodes are missing on kernels without CONFIG_NUMA
+        for (node, path) in numbered_entries(&system.join("node"), "node").unwrap_or_default() {
+            for id in read_cpulist(&path.join("cpulist")).unwrap_or_default() {
+                if let Some(cpu) = cpus.get_mut(&id) {
+                    cpu.node = Some(node);
+                }
+            }
+        }
+
+        if cpus.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("No cpus found in {}", system.display())));
+        }
+
+        Ok(Self { cpus })
+    }
+
+    pub fn get(&self, id: usize) -> Option<&CpuTopology> {
+        self.cpus.get(&id)
+    }
+
+    pub fn cpus(&self) -> impl Iterator<Item = &CpuTopology> {
+        self.cpus.values()
+    }
+
+    /// The cpus of a NUMA node
+    pub fn node_cpus(&self, node: usize) -> Vec<usize> {
+        self.cpus().filter(|c| c.node == Some(node)).map(|c| c.id).collect()
+    }
+
+    /// The cpus of a physical package
+    pub fn socket_cpus(&self, socket: usize) -> Vec<usize> {
+        self.cpus().filter(|c| c.package == Some(socket)).map(|c| c.id).collect()
+    }
+
+    /// Whether two distinct logical cpus are hyper-threads of the same physical core
+    pub fn are_smt_siblings(&self, a: usize, b: usize) -> bool {
+        a != b && self.get(a).is_some_and(|c| c.smt_siblings.contains(&b))
+    }
+
+    pub fn relationship(&self, a: usize, b: usize) -> Relationship {
+        let (ta, tb) = match (self.get(a), self.get(b)) {
+            (Some(ta), Some(tb)) => (ta, tb),
+            _ => return Relationship::CrossSocket,
+        };
+        let shared = |f: fn(&CpuTopology) -> Option<usize>| f(ta).is_some() && f(ta) == f(tb);
+
+        if self.are_smt_siblings(a, b) {
+            Relationship::SmtSiblings
+        } else if shared(|t| t.l2) {
+            Relationship::SharedL2
+        } else if shared(|t| t.l3) {
+            Relationship::SharedL3
+        } else if ta.package != tb.package {
+            Relationship::CrossSocket
+        } else if shared(|t| t.die) {
+            Relationship::SameDie
+        } else {
+            Relationship::SameSocket
+        }
+    }
+
+    pub fn boundary(&self, a: usize, b: usize) -> Boundary {
+        match (self.get(a), self.get(b)) {
+            (Some(a), Some(b)) if a.package != b.package => Boundary::Socket,
+            (Some(a), Some(b)) if a.l3 != b.l3 => Boundary::L3,
+            _ => Boundary::None,
+        }
+    }
+}
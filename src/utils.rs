@@ -1,6 +1,8 @@
 use std::time::Duration;
 use quanta::Clock;
+use core_affinity::CoreId;
 use crate::bench::Count;
+use crate::error::{Error, Result};
 
 pub fn black_box<T>(dummy: T) -> T {
     unsafe { std::ptr::read_volatile(&dummy) }
@@ -41,16 +43,48 @@ pub fn get_cpuid() -> Option<raw_cpuid::CpuId> {
     None
 }
 
-pub fn assert_rdtsc_usable(clock: &quanta::Clock) {
-    let cpuid = get_cpuid().expect("This benchmark is only compatible with x86");
+pub fn check_rdtsc_usable(clock: &quanta::Clock) -> Result<()> {
+    let cpuid = get_cpuid().ok_or_else(|| Error::Clock("This benchmark is only compatible with x86".to_string()))?;
 
-    assert!(cpuid.get_advanced_power_mgmt_info().expect("CPUID failed").has_invariant_tsc(),
-        "This benchmark only runs with a TscInvariant=true");
+    let invariant_tsc = cpuid.get_advanced_power_mgmt_info().map(|info| info.has_invariant_tsc());
+    if invariant_tsc != Some(true) {
+        return Err(Error::Clock("This benchmark only runs with a TscInvariant=true".to_string()));
+    }
 
     const NUM_ITERS: Count = 10_000;
-    let clock_read_overhead = clock_read_overhead_sum(&clock, NUM_ITERS).as_nanos() as f64 / NUM_ITERS as f64;
-    eprintln!("Reading the clock via RDTSC takes {:.2}ns", clock_read_overhead);
-    assert!((0.1..1000.0).contains(&clock_read_overhead), "The timing to read the clock is either not-consistant or too slow");
+    let clock_read_overhead = clock_read_overhead_sum(clock, NUM_ITERS).as_nanos() as f64 / NUM_ITERS as f64;
+    if !(0.1..1000.0).contains(&clock_read_overhead) {
+        return Err(Error::Clock(format!("The timing to read the clock is either not-consistant or too slow ({:.2}ns)", clock_read_overhead)));
+    }
+    Ok(())
+}
+
+/// Checks that there are at least 2 cores to measure, and that a thread can be pinned to each of them
+pub fn check_cores(cores: &[CoreId]) -> Result<()> {
+    if cores.len() < 2 {
+        let ids = cores.iter().map(|c| c.id.to_string()).collect::<Vec<_>>();
+        return Err(Error::InvalidArgs(format!("At least 2 cores are needed, got [{}]", ids.join(", "))));
+    }
+    check_affinity(cores)
+}
+
+/// Pins a thread to each core and checks that it runs there. Pinning fails silently otherwise,
+/// e.g., with cores outside of the cpuset of the process.
+/// Only Linux reports the affinity of the current thread, on other systems this always succeeds.
+pub fn check_affinity(cores: &[CoreId]) -> Result<()> {
+    if !cfg!(target_os = "linux") {
+        return Ok(());
+    }
+    for &core in cores {
+        let pinned = std::thread::spawn(move || {
+            core_affinity::set_for_current(core);
+            core_affinity::get_core_ids().map(|ids| ids.iter().map(|c| c.id).collect::<Vec<_>>())
+        }).join().map_err(Error::thread_panicked)?;
+        if pinned != Some(vec![core.id]) {
+            return Err(Error::Affinity(format!("Failed to pin a thread to core {}, it may be outside of the cpuset of this process", core.id)));
+        }
+    }
+    Ok(())
 }
 
 pub fn get_cpu_brand() -> Option<String> {
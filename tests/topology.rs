@@ -0,0 +1,75 @@
+use core_affinity::CoreId;
+use core_to_core_latency::cpulist::CoreOrder;
+use core_to_core_latency::topology::{Boundary, Relationship, Topology};
+use std::path::Path;
+
+/// 2 sockets of 4 cores with 2 hyper-threads each. cpu N and N+8 are the hyper-threads of a core.
+/// Each socket has 2 L3 domains of 2 cores, and is a NUMA node.
+fn two_sockets() -> Topology {
+    Topology::from_sysfs(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/two-sockets"))).unwrap()
+}
+
+#[test]
+fn from_sysfs() {
+    let topology = two_sockets();
+    assert_eq!(topology.cpus().count(), 16);
+
+    let cpu = topology.get(13).unwrap();
+    assert_eq!(cpu.package, Some(1));
+    assert_eq!(cpu.die, Some(0));
+    assert_eq!(cpu.core, Some(1));
+    assert_eq!(cpu.smt_siblings, vec![5, 13]);
+    assert_eq!(cpu.l2, Some(5));
+    assert_eq!(cpu.l3, Some(4));
+    assert_eq!(cpu.node, Some(1));
+
+    assert_eq!(topology.socket_cpus(0), vec![0, 1, 2, 3, 8, 9, 10, 11]);
+    assert_eq!(topology.node_cpus(1), vec![4, 5, 6, 7, 12, 13, 14, 15]);
+}
+
+#[test]
+fn missing_sysfs() {
+    assert!(Topology::from_sysfs(Path::new("/nonexistent")).is_err());
+}
+
+#[test]
+fn relationship() {
+    let topology = two_sockets();
+    assert_eq!(topology.relationship(0, 8), Relationship::SmtSiblings);
+    assert_eq!(topology.relationship(0, 9), Relationship::SharedL3);
+    assert_eq!(topology.relationship(0, 2), Relationship::SameDie);
+    assert_eq!(topology.relationship(0, 4), Relationship::CrossSocket);
+    assert_eq!(topology.relationship(3, 12), Relationship::CrossSocket);
+}
+
+#[test]
+fn boundary() {
+    let topology = two_sockets();
+    assert_eq!(topology.boundary(0, 9), Boundary::None);
+    assert_eq!(topology.boundary(1, 2), Boundary::L3);
+    assert_eq!(topology.boundary(3, 4), Boundary::Socket);
+}
+
+#[test]
+fn topology_order() {
+    let topology = two_sockets();
+    let mut cores = (0..16).rev().map(|id| CoreId { id }).collect::<Vec<_>>();
+    CoreOrder::Topology.sort(&mut cores, Some(&topology));
+    let ids = cores.iter().map(|c| c.id).collect::<Vec<_>>();
+    // Socket, then L3 domain, then physical core
+    assert_eq!(ids, vec![0, 8, 1, 9, 2, 10, 3, 11, 4, 12, 5, 13, 6, 14, 7, 15]);
+}
+
+#[test]
+fn schedule_batches() {
+    let topology = two_sockets();
+    let cores = (0..8).map(|id| CoreId { id }).collect::<Vec<_>>();
+    let pairs = vec![(1, 0), (3, 2), (5, 4), (2, 0)];
+
+    // (3,2) shares the socket of (1,0) but not its L3, (5,4) is on the other socket
+    let batches = core_to_core_latency::pairs::schedule_batches(pairs.clone(), &cores, Some(&topology), 2);
+    assert_eq!(batches, vec![vec![(1, 0), (5, 4)], vec![(3, 2)], vec![(2, 0)]]);
+
+    let batches = core_to_core_latency::pairs::schedule_batches(pairs, &cores, Some(&topology), 1);
+    assert_eq!(batches, vec![vec![(1, 0)], vec![(3, 2)], vec![(5, 4)], vec![(2, 0)]]);
+}